@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+
+use anyhow::anyhow;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+use crate::database::{CommonQueries, DbError, DbPool};
+use crate::reddit::{self, CLIENT, PostDetails, RedditData, RedditDataPostData, Sort, Time};
+use crate::{ARCHIVE_DIR, BASE_URL};
+
+static ARCHIVE_QUEUE: RwLock<Option<UnboundedSender<RedditDataPostData>>> = RwLock::new(None);
+/// Names of the archived files by post ID, so rendering a page doesn't touch the file system.
+static ARCHIVED_FILES: LazyLock<RwLock<HashMap<String, Vec<String>>>> = LazyLock::new(Default::default);
+
+/// Download the media of all starred posts, then of posts starred later (see [`queue`]).
+/// Does nothing unless an archive directory is configured.
+pub fn work(db: DbPool) {
+	let Some(dir) = ARCHIVE_DIR.as_deref() else {
+		return;
+	};
+	let (tx, mut rx) = unbounded_channel();
+	ARCHIVE_QUEUE.write().unwrap().replace(tx);
+	if let Ok(entries) = fs::read_dir(dir) {
+		for name in entries.flatten().map(|x| x.file_name().to_string_lossy().into_owned()) {
+			if !name.ends_with(".tmp") {
+				record_archived(dir, &name);
+			}
+		}
+	}
+	match starred_posts(&db) {
+		Ok(posts) => {
+			for post in posts {
+				archive_post(dir, &post);
+			}
+		},
+		Err(e) => tracing::warn!("archive: failed to load starred posts: {e:?}"),
+	}
+	while let Some(post) = rx.blocking_recv() {
+		archive_post(dir, &post);
+	}
+}
+
+fn starred_posts(db: &DbPool) -> Result<Vec<RedditDataPostData>, DbError> {
+	let mut db = db.get()?;
+	let tx = db.transaction()?;
+	tx.get_all_posts()
+}
+
+/// Archive the media of the post, unless archiving is disabled.
+pub fn queue(post: RedditDataPostData) {
+	if let Some(tx) = ARCHIVE_QUEUE.read().unwrap().as_ref() {
+		let _ = tx.send(post);
+	}
+}
+
+fn record_archived(dir: &Path, id: &str) {
+	let Ok(entries) = fs::read_dir(dir.join(id)) else {
+		return;
+	};
+	let files = entries
+		.flatten()
+		.map(|x| x.file_name().to_string_lossy().into_owned())
+		.collect();
+	ARCHIVED_FILES.write().unwrap().insert(id.to_owned(), files);
+}
+
+/// Name of the archived file of the media item, `index` counting the media items of the post (for galleries).
+fn archived_name(id: &str, index: usize) -> Option<String> {
+	let prefix = format!("{index}.");
+	let files = ARCHIVED_FILES.read().unwrap();
+	files.get(id)?.iter().find(|x| x.starts_with(&prefix)).cloned()
+}
+
+/// URL of the archived media, `index` counting the media items of the post (for galleries).
+pub fn archived_url(id: &str, index: usize) -> Option<String> {
+	let file = archived_name(id, index)?;
+	Some(format!("{}/archive/{id}/{file}", *BASE_URL))
+}
+
+/// Path of the archived image of the post (its first media item), if any.
+pub fn archived_image(id: &str) -> Option<PathBuf> {
+	let dir = ARCHIVE_DIR.as_deref()?;
+	let file = archived_name(id, 0).filter(|x| content_type(x).starts_with("image/"))?;
+	Some(dir.join(id).join(file))
+}
+
+/// Content type of an archived file, by its extension.
+pub fn content_type(file: &str) -> &'static str {
+	match file.rsplit('.').next() {
+		Some("png") => "image/png",
+		Some("gif") => "image/gif",
+		Some("webp") => "image/webp",
+		Some("mp4") => "video/mp4",
+		Some("ts") => "video/mp2t",
+		Some("m4s") => "video/iso.segment",
+		Some("aac") => "audio/aac",
+		Some("m3u8") => "application/vnd.apple.mpegurl",
+		_ => "image/jpeg",
+	}
+}
+
+/// Path of an archived file, if the name is valid and the file exists.
+pub fn archived_file(id: &str, file: &str) -> Option<PathBuf> {
+	let dir = ARCHIVE_DIR.as_deref()?;
+	let valid_id = !id.is_empty() && id.chars().all(|x| x.is_ascii_alphanumeric());
+	// the media index, followed by the stream and segment for HLS (e.g. "0_video_3.ts")
+	let (name, extension) = file.split_once('.')?;
+	let valid_file = name.starts_with(|x: char| x.is_ascii_digit())
+		&& name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+		&& extension.chars().all(|x| x.is_ascii_alphanumeric());
+	let path = dir.join(id).join(file);
+	(valid_id && valid_file && path.is_file()).then_some(path)
+}
+
+/// Media is downloaded into a temporary directory first, so an existing
+/// directory means the post is archived.
+fn archive_post(dir: &Path, post: &RedditDataPostData) {
+	let target = dir.join(&post.id);
+	if target.exists() {
+		return;
+	}
+	let tmp = dir.join(format!("{}.tmp", post.id));
+	let res = fs::create_dir_all(&tmp)
+		.map_err(anyhow::Error::from)
+		.and_then(|_| download_media(&tmp, post))
+		.and_then(|_| Ok(fs::rename(&tmp, &target)?));
+	match res {
+		Ok(()) => {
+			record_archived(dir, &post.id);
+			tracing::debug!("archived {}", post.id);
+		},
+		Err(e) => {
+			tracing::warn!("failed to archive {}: {e}", post.id);
+			let _ = fs::remove_dir_all(&tmp);
+		},
+	}
+}
+
+fn download_media(dir: &Path, post: &RedditDataPostData) -> Result<(), anyhow::Error> {
+	let posts = reddit::parse_json(
+		RedditData::from_posts(vec![post.clone()]),
+		"archive",
+		Sort::Hot,
+		Time::All,
+	)?;
+	if posts.is_empty() {
+		return Err(anyhow!("no media"));
+	}
+	for (index, p) in posts.into_iter().enumerate() {
+		match p.details {
+			PostDetails::Image { src_url, sizes } => {
+				let url = sizes
+					.into_iter()
+					.max_by_key(|x| x.width)
+					.map(|x| x.src_url)
+					.unwrap_or(src_url);
+				download(&url, &dir.join(format!("{index}.{}", extension(&url).unwrap_or("jpg"))))?;
+			},
+			PostDetails::VideoMp4 { mp4_urls } => {
+				// prefer the last URL (for redgifs, the plain mp4 file)
+				let path = dir.join(format!("{index}.mp4"));
+				let mut res = Err(anyhow!("no video URL"));
+				for url in mp4_urls.iter().rev() {
+					res = download(url, &path);
+					if res.is_ok() {
+						break;
+					}
+				}
+				res?;
+			},
+			PostDetails::Video { hls_url } => download_hls(&hls_url, dir, index)?,
+			// nothing to download
+			PostDetails::VideoEmbed { .. } => {},
+		}
+	}
+	Ok(())
+}
+
+fn download(url: &str, path: &Path) -> Result<(), anyhow::Error> {
+	let mut res = CLIENT.get(url).call()?;
+	let mut file = File::create(path)?;
+	io::copy(&mut res.body_mut().as_reader(), &mut file)?;
+	Ok(())
+}
+
+/// File extension of the URL, if it looks like one.
+fn extension(url: &str) -> Option<&str> {
+	let path = url.split(['?', '#']).next().unwrap_or(url);
+	let file = path.rsplit('/').next().unwrap_or(path);
+	match file.rsplit_once('.') {
+		Some((_, ext)) if !ext.is_empty() && ext.len() <= 4 && ext.chars().all(|x| x.is_ascii_alphanumeric()) => {
+			Some(ext)
+		},
+		_ => None,
+	}
+}
+
+/// Resolve a (possibly relative) URL found in a playlist.
+fn resolve_url(base: &str, url: &str) -> String {
+	if url.starts_with("http://") || url.starts_with("https://") {
+		return url.to_owned();
+	}
+	let base = base.split('?').next().unwrap_or(base);
+	match base.rfind('/') {
+		Some(idx) => format!("{}{url}", &base[..=idx]),
+		None => url.to_owned(),
+	}
+}
+
+/// Download the best video stream of the HLS playlist together with its audio stream,
+/// and store a playlist referring to the local copies (played with hls.js, like the original).
+fn download_hls(url: &str, dir: &Path, index: usize) -> Result<(), anyhow::Error> {
+	let master = CLIENT.get(url).call()?.body_mut().read_to_string()?;
+	let mut best: Option<(u64, &str, &str)> = None;
+	let mut lines = master.lines();
+	while let Some(line) = lines.next() {
+		let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") else {
+			continue;
+		};
+		let bandwidth = attribute(attributes, "BANDWIDTH")
+			.and_then(|x| x.parse().ok())
+			.unwrap_or(0);
+		if let Some(uri) = lines.next()
+			&& best.is_none_or(|x| x.0 < bandwidth)
+		{
+			best = Some((bandwidth, line, uri.trim()));
+		}
+	}
+	let Some((_, stream_inf, uri)) = best else {
+		// not a master playlist: the URL already points to the media playlist
+		return download_hls_media(url, dir, &index.to_string());
+	};
+	let mut playlist = master
+		.lines()
+		.filter(|x| *x == "#EXTM3U" || x.starts_with("#EXT-X-VERSION:") || *x == "#EXT-X-INDEPENDENT-SEGMENTS")
+		.map(|x| format!("{x}\n"))
+		.collect::<String>();
+	// audio is a separate stream, unless it is muxed into the video
+	let audio = attribute(&stream_inf["#EXT-X-STREAM-INF:".len()..], "AUDIO").and_then(|group| {
+		master.lines().find(|x| {
+			x.strip_prefix("#EXT-X-MEDIA:")
+				.is_some_and(|x| attribute(x, "TYPE") == Some("AUDIO") && attribute(x, "GROUP-ID") == Some(group))
+		})
+	});
+	if let Some(media) = audio {
+		match attribute(&media["#EXT-X-MEDIA:".len()..], "URI") {
+			Some(audio_uri) => {
+				let prefix = format!("{index}_audio");
+				download_hls_media(&resolve_url(url, audio_uri), dir, &prefix)?;
+				playlist += &media.replace(audio_uri, &format!("{prefix}.m3u8"));
+			},
+			None => playlist += media,
+		}
+		playlist += "\n";
+	}
+	let prefix = format!("{index}_video");
+	download_hls_media(&resolve_url(url, uri), dir, &prefix)?;
+	playlist += &format!("{stream_inf}\n{prefix}.m3u8\n");
+	fs::write(dir.join(format!("{index}.m3u8")), playlist)?;
+	Ok(())
+}
+
+/// Download the segments of an HLS media playlist and store the playlist as `{prefix}.m3u8`,
+/// referring to the local copies.
+fn download_hls_media(url: &str, dir: &Path, prefix: &str) -> Result<(), anyhow::Error> {
+	let media = CLIENT.get(url).call()?.body_mut().read_to_string()?;
+	// segments can be byte ranges of the same file, which is downloaded once
+	let mut files = HashMap::<String, String>::new();
+	let mut local = |uri: &str| -> Result<String, anyhow::Error> {
+		let segment = resolve_url(url, uri);
+		if let Some(name) = files.get(&segment) {
+			return Ok(name.clone());
+		}
+		let name = format!("{prefix}_{}.{}", files.len(), extension(&segment).unwrap_or("ts"));
+		download(&segment, &dir.join(&name))?;
+		files.insert(segment, name.clone());
+		Ok(name)
+	};
+	let mut playlist = String::new();
+	for line in media.lines().map(str::trim) {
+		if let Some(map) = line.strip_prefix("#EXT-X-MAP:")
+			&& let Some(uri) = attribute(map, "URI")
+		{
+			// initialization segment of fragmented mp4 streams
+			playlist += &line.replace(uri, &local(uri)?);
+		} else if !line.is_empty() && !line.starts_with('#') {
+			playlist += &local(line)?;
+		} else {
+			playlist += line;
+		}
+		playlist += "\n";
+	}
+	if files.is_empty() {
+		return Err(anyhow!("empty HLS playlist"));
+	}
+	fs::write(dir.join(format!("{prefix}.m3u8")), playlist)?;
+	Ok(())
+}
+
+/// Value of an attribute in an HLS attribute list, without the quotes.
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+	let mut rest = attributes;
+	while !rest.is_empty() {
+		let (key, value) = rest.split_once('=')?;
+		let (value, next) = match value.strip_prefix('"') {
+			Some(quoted) => {
+				let (value, next) = quoted.split_once('"')?;
+				(value, next.strip_prefix(',').unwrap_or(next))
+			},
+			None => value.split_once(',').unwrap_or((value, "")),
+		};
+		if key.trim() == name {
+			return Some(value);
+		}
+		rest = next;
+	}
+	None
+}
+
+#[test]
+fn archive_url_test() {
+	assert_eq!(extension("https://i.redd.it/abc.png"), Some("png"));
+	assert_eq!(
+		extension("https://preview.redd.it/abc.jpeg?width=640&s=x"),
+		Some("jpeg")
+	);
+	assert_eq!(extension("https://v.redd.it/abc/DASH_720"), None);
+	let stream_inf = r#"BANDWIDTH=1200000,CODECS="avc1.4d401f,mp4a.40.2",AUDIO="audio-1""#;
+	assert_eq!(attribute(stream_inf, "CODECS"), Some("avc1.4d401f,mp4a.40.2"));
+	assert_eq!(attribute(stream_inf, "AUDIO"), Some("audio-1"));
+	assert_eq!(attribute(stream_inf, "BANDWIDTH"), Some("1200000"));
+	assert_eq!(attribute(stream_inf, "URI"), None);
+	assert_eq!(
+		resolve_url("https://v.redd.it/abc/HLSPlaylist.m3u8?a=1", "HLS_720.m3u8"),
+		"https://v.redd.it/abc/HLS_720.m3u8"
+	);
+	assert_eq!(
+		resolve_url("https://v.redd.it/abc/x.m3u8", "https://cdn/y.ts"),
+		"https://cdn/y.ts"
+	);
+}
@@ -1,13 +1,15 @@
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::io::SeekFrom;
 use std::net::SocketAddr;
-use std::sync::LazyLock;
+use std::panic;
 use std::thread;
 use std::time::SystemTime;
 
-use axum::extract::{Path, Query, RawQuery, Request};
-use axum::http::{StatusCode, header};
+use axum::body::Body;
+use axum::extract::{Path, Query, RawQuery, Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
 use axum::middleware::{self, Next};
 use axum::response::{Html, IntoResponse, Redirect, Response};
 use axum::routing::{get, post};
@@ -15,12 +17,20 @@ use axum::{Extension, Json, Router};
 use axum_client_ip::{ClientIp, ClientIpSource};
 use itertools::Itertools;
 use petname::{Generator, Petnames};
-use reddit_image_grid::database::{CommonQueries, DB};
-use reddit_image_grid::reddit::{self, RedditData, RedditDataPostData, Time};
-use reddit_image_grid::template::TemplateParameters;
-use reddit_image_grid::{BASE_URL, PORT, StringError, UppercaseFirst, template, with_db};
-use serde::Deserialize;
-use tokio::sync::RwLock;
+use reddit_image_grid::database::{
+	CommonQueries, DbError, DbPool, Group, Member, Permission, StarInfo, StarOrder, StarredPost, Visibility,
+};
+use reddit_image_grid::reddit::{self, PostNotFound, RedditData, RedditError, Sort, Time};
+use reddit_image_grid::refresh::RefreshResult;
+use reddit_image_grid::template::{DEFAULT_COLUMNS, Layout, TemplateParameters};
+use reddit_image_grid::{
+	BASE_URL, PORT, StringError, UppercaseFirst, archive, export, gen_token, import, normalize_tag, refresh, template,
+};
+use rusqlite::Transaction;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::oneshot;
+use tokio_util::io::ReaderStream;
 use tower_http::catch_panic::CatchPanicLayer;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
@@ -29,7 +39,7 @@ type Result<T> = std::result::Result<T, AppError>;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-	thread::spawn(|| reddit::work());
+	thread::spawn(reddit::work);
 	tracing_subscriber::registry()
 		.with(
 			tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -44,7 +54,60 @@ async fn main() {
 		)
 		.with(tracing_subscriber::fmt::layer())
 		.init();
-	real_main().await;
+	let args: Vec<String> = std::env::args().collect();
+	match args.get(1).map(String::as_str) {
+		None => {
+			let pool = match DbPool::new() {
+				Ok(pool) => pool,
+				Err(e) => {
+					tracing::error!("failed to open database: {e}");
+					std::process::exit(1);
+				},
+			};
+			let archive_pool = pool.clone();
+			thread::spawn(move || archive::work(archive_pool));
+			let refresh_pool = pool.clone();
+			thread::spawn(move || refresh::work(refresh_pool));
+			real_main(pool).await
+		},
+		Some("import") if args.len() == 4 => {
+			if let Err(e) = import_cli(&args[2], &args[3]).await {
+				tracing::error!("import failed: {e}");
+				std::process::exit(1);
+			}
+		},
+		_ => {
+			eprintln!("usage: server [import GROUP FILE]");
+			std::process::exit(1);
+		},
+	}
+}
+
+/// Import posts into the group, creating it if needed. FILE may be - to read from stdin.
+async fn import_cli(group: &str, file: &str) -> std::result::Result<(), Box<dyn Error>> {
+	let input = if file == "-" {
+		std::io::read_to_string(std::io::stdin())?
+	} else {
+		std::fs::read_to_string(file)?
+	};
+	let mut list = import::parse(&input);
+	for x in &list.unrecognized {
+		tracing::warn!("not a post: {x}");
+	}
+	for id in import::resolve(&mut list.items).await? {
+		tracing::warn!("post not found: {id}");
+	}
+	let mut db = DbPool::new()?.get()?;
+	let tx = db.transaction()?;
+	if !tx.group_exists(group)? {
+		check_group_name(group)?;
+		let token = tx.create_group(group, Visibility::Public)?;
+		tracing::info!("created star list {group}, edit token: {token}");
+	}
+	let count = import::insert(&tx, group, list.items, None)?;
+	tx.commit()?;
+	tracing::info!("imported {count} posts into {group}");
+	Ok(())
 }
 
 #[cfg(feature = "proxy")]
@@ -57,22 +120,47 @@ fn ip_extractor() -> Extension<ClientIpSource> {
 	ClientIpSource::ConnectInfo.into_extension()
 }
 
-async fn real_main() {
+async fn real_main(pool: DbPool) {
 	reddit_image_grid::force_lazy_vars();
 	let app = Router::new()
 		.route("/", get(root))
 		.route("/favicon.png", get(favicon))
 		.route("/hls.min.js", get(hls_js))
+		.route("/metrics", get(metrics))
+		.route("/archive/{id}/{file}", get(archived_media))
 		.route("/render", post(render_json))
 		.route("/r/{sub}", get(root_with_sub_redirect))
 		.route("/r/{sub}/", get(root_with_sub))
 		.route("/r/{sub}/{sort}", get(root_with_sub_sort_redirect))
 		.route("/r/{sub}/{sort}/", get(root_with_sub_sort))
+		.route("/s/", get(star_group_index))
+		.route("/s/info", post(star_group_info))
+		.route("/s/search", get(star_search))
+		.route("/s/all/", get(star_all))
 		.route("/s/{group}/", get(star_group))
+		.route("/s/{group}/export.json", get(star_group_export_json))
+		.route("/s/{group}/export.csv", get(star_group_export_csv))
+		.route("/s/{group}/export.html", get(star_group_export_html))
 		.route("/s/{group}/add/{id}", post(star_group_submit))
+		.route("/s/{group}/remove/{id}", post(star_group_remove))
+		.route("/s/{group}/move/{id}/{target}", post(star_group_move))
+		.route("/s/{group}/import", post(star_group_import))
+		.route("/s/{group}/refresh", post(star_group_refresh))
+		.route("/s/{group}/reorder/{id}/{position}", post(star_group_reorder))
+		.route("/s/{group}/tag/{id}/{tag}", post(star_group_tag))
+		.route("/s/{group}/untag/{id}/{tag}", post(star_group_untag))
+		.route("/s/{group}/visibility/{visibility}", post(star_group_visibility))
+		.route("/s/{group}/rename/{new_name}", post(star_group_rename))
+		.route("/s/{group}/describe", post(star_group_describe))
+		.route("/s/{group}/delete", post(star_group_delete))
+		.route("/s/{group}/members", get(star_group_members))
+		.route("/s/{group}/members/add/{name}", post(star_group_add_member))
+		.route("/s/{group}/members/remove/{name}", post(star_group_remove_member))
+		.with_state(pool)
 		.layer(middleware::from_fn(log_time))
 		.layer(ip_extractor())
-		.layer(CatchPanicLayer::custom(handle_panic));
+		.layer(CatchPanicLayer::custom(handle_panic))
+		.layer(middleware::from_fn(error_page));
 
 	let bind_addr = format!("0.0.0.0:{}", *PORT);
 	let listener = tokio::net::TcpListener::bind(bind_addr)
@@ -86,29 +174,335 @@ async fn real_main() {
 		.expect("failed to start axum");
 }
 
-// TODO: populate post cache on server fetch
-static POST_CACHE: LazyLock<RwLock<HashMap<String, RedditDataPostData>>> =
-	LazyLock::new(|| RwLock::new(HashMap::new()));
+async fn star_group_index(State(pool): State<DbPool>) -> Result<Html<String>> {
+	let groups = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			let groups = tx.list_public_groups()?;
+			tx.commit()?;
+			Ok(groups)
+		})
+		.await?;
+	Ok(Html(template::get_group_index(groups)?))
+}
 
-async fn star_group(Path(group): Path<String>) -> Result<Html<String>> {
-	let res = with_db!(|db: &mut DB| {
-		let tx = db.transaction()?;
-		let res = tx.get_posts_in_group(&group)?;
-		tx.commit()?;
-		Ok(res)
-	})?;
-	let to_render = RedditData::from_posts(res);
-	{
-		let mut cache = POST_CACHE.write().await;
-		for x in to_render.posts() {
-			cache.insert(x.id.clone(), x.clone());
-		}
+#[derive(Deserialize)]
+struct GroupInfoRequest {
+	name: String,
+	token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct GroupInfo {
+	/// Name the client asked for, differs from `name` if the group was renamed.
+	requested: String,
+	name: String,
+	title: Option<String>,
+	visibility: &'static str,
+	post_count: usize,
+	editable: bool,
+}
+
+/// Details of the star lists known to the browser.
+/// Lists that do not exist (or are private and the token is wrong) are omitted.
+async fn star_group_info(
+	State(pool): State<DbPool>,
+	Json(groups): Json<Vec<GroupInfoRequest>>,
+) -> Result<Json<Vec<GroupInfo>>> {
+	let infos = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			let mut infos = vec![];
+			for request in groups {
+				let mut name = request.name.clone();
+				if !tx.group_exists(&name)? {
+					match tx.resolve_group_alias(&name)? {
+						Some(new_name) => name = new_name,
+						None => continue,
+					}
+				}
+				let Some(summary) = tx.get_group_summary(&name)? else {
+					continue;
+				};
+				let editable = tx.check_token(&name, request.token.as_deref(), Permission::Add).is_ok();
+				if summary.group.visibility == Visibility::Private && !editable {
+					continue;
+				}
+				infos.push(GroupInfo {
+					requested: request.name,
+					name,
+					title: summary.group.title,
+					visibility: summary.group.visibility.id(),
+					post_count: summary.post_count,
+					editable,
+				});
+			}
+			tx.commit()?;
+			Ok(infos)
+		})
+		.await?;
+	Ok(Json(infos))
+}
+
+async fn star_group(
+	State(pool): State<DbPool>,
+	Path(group): Path<String>,
+	Query(query): Query<Q>,
+	RawQuery(raw_query): RawQuery,
+	headers: HeaderMap,
+) -> Result<Response> {
+	let order = query.order()?;
+	let token = query
+		.token
+		.clone()
+		.or(header_value(&headers, EDIT_TOKEN).map(str::to_owned));
+	let (name, alias_token) = (group.clone(), token.clone());
+	let renamed = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			if tx.group_exists(&name)? {
+				return Ok(None);
+			}
+			let Some(new_name) = tx.resolve_group_alias(&name)? else {
+				return Ok(None);
+			};
+			// do not reveal the new name of private star lists
+			viewable_group(&tx, &new_name, alias_token.as_deref())?;
+			Ok(Some(new_name))
+		})
+		.await?;
+	if let Some(new_name) = renamed {
+		let q_mark = if raw_query.is_some() { "?" } else { "" };
+		return Ok(Redirect::temporary(&format!(
+			"{}/s/{new_name}/{q_mark}{}",
+			*BASE_URL,
+			raw_query.unwrap_or_default()
+		))
+		.into_response());
+	}
+	let tag = query.tag.clone().filter(|x| !x.is_empty());
+	let (group, mut res, tags) = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			let group = viewable_group(&tx, &group, token.as_deref())?;
+			let res = tx.get_posts_in_group(&group.name, order)?;
+			let tags = tx.get_group_tags(&group.name)?;
+			tx.commit()?;
+			Ok((group, res, tags))
+		})
+		.await?;
+	reddit::cache_posts(res.iter().map(|x| &x.post)).await;
+	if let Some(tag) = &tag {
+		res.retain(|x| x.info.tags.contains(tag));
+	}
+	Ok(Html(
+		template::get(
+			TemplateParameters::render_ui_stars(
+				group,
+				query.autoplay.unwrap_or(false),
+				query.layout()?,
+				query.columns(),
+				order,
+				res,
+			)
+			.with_tags(tags, tag),
+		)
+		.await?,
+	)
+	.into_response())
+}
+
+/// Search the public star lists and those the browser knows the edit token of.
+async fn star_search(State(pool): State<DbPool>, Query(query): Query<Q>, headers: HeaderMap) -> Result<Html<String>> {
+	let input = query.q.clone().unwrap_or_default();
+	let tokens = known_tokens(&headers);
+	let words = input.clone();
+	let posts = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			let posts = tx.search_posts(&words, &tokens)?;
+			tx.commit()?;
+			Ok(posts)
+		})
+		.await?;
+	reddit::cache_posts(posts.iter().map(|x| &x.post)).await;
+	let description = match posts.len() {
+		_ if input.trim().is_empty() => "Enter some words to search for.".to_owned(),
+		0 => "No starred posts found.".to_owned(),
+		1 => "1 starred post found.".to_owned(),
+		n => format!("{n} starred posts found."),
+	};
+	let group = Group {
+		name: "search".to_owned(),
+		visibility: Visibility::Public,
+		created_at: None,
+		title: Some(format!("Search: {input}")),
+		description: Some(description),
+	};
+	Ok(Html(
+		template::get(
+			TemplateParameters::render_ui_stars(
+				group,
+				query.autoplay.unwrap_or(false),
+				query.layout()?,
+				query.columns(),
+				StarOrder::Position,
+				posts,
+			)
+			.read_only(),
+		)
+		.await?,
+	))
+}
+
+/// All posts of the star lists the browser knows the edit token of (or of all public star lists),
+/// each only once.
+async fn star_all(State(pool): State<DbPool>, Query(query): Query<Q>, headers: HeaderMap) -> Result<Html<String>> {
+	let tokens = known_tokens(&headers);
+	let all_public = tokens.is_empty();
+	let mut posts = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			let posts = tx.get_posts_in_groups(&tokens)?;
+			tx.commit()?;
+			Ok(posts)
+		})
+		.await?;
+	let mut counts = HashMap::<_, usize>::new();
+	for post in &posts {
+		*counts.entry(post.group.clone()).or_default() += 1;
+	}
+	let mut facets: Vec<_> = counts.into_iter().collect();
+	facets.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+	let list = query.list.clone().filter(|x| !x.is_empty());
+	if let Some(list) = &list {
+		posts.retain(|x| &x.group == list);
 	}
+	let mut seen = HashSet::new();
+	posts.retain(|x| seen.insert(x.post.id.clone()));
+	reddit::cache_posts(posts.iter().map(|x| &x.post)).await;
+	let group = Group {
+		name: "all".to_owned(),
+		visibility: Visibility::Public,
+		created_at: None,
+		title: Some(if all_public { "All public stars" } else { "All my stars" }.to_owned()),
+		description: None,
+	};
 	Ok(Html(
-		template::get(TemplateParameters::render_ui_stars(group, false, to_render)).await?,
+		template::get(
+			TemplateParameters::render_ui_stars(
+				group,
+				query.autoplay.unwrap_or(false),
+				query.layout()?,
+				query.columns(),
+				StarOrder::Starred,
+				posts,
+			)
+			.with_group_facets(facets, list)
+			.read_only(),
+		)
+		.await?,
 	))
 }
 
+/// Get the group, unless it is private and the token is not its edit token.
+fn viewable_group(tx: &Transaction, group: &str, token: Option<&str>) -> std::result::Result<Group, DbError> {
+	let Some(group) = tx.get_group(group)? else {
+		return Err(DbError::NotFound("star list not found"));
+	};
+	if group.visibility == Visibility::Private && tx.check_token(&group.name, token, Permission::Add).is_err() {
+		return Err(DbError::NotFound("star list not found"));
+	}
+	Ok(group)
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+	token: Option<String>,
+}
+
+async fn load_export(
+	pool: &DbPool,
+	group: String,
+	query: &ExportQuery,
+	headers: &HeaderMap,
+) -> std::result::Result<(Group, Vec<StarredPost>), DbError> {
+	let token = query
+		.token
+		.clone()
+		.or(header_value(headers, EDIT_TOKEN).map(str::to_owned));
+	pool.run(move |db| {
+		let tx = db.transaction()?;
+		let group = viewable_group(&tx, &group, token.as_deref())?;
+		let posts = tx.get_posts_in_group(&group.name, StarOrder::Position)?;
+		tx.commit()?;
+		Ok((group, posts))
+	})
+	.await
+}
+
+fn attachment(name: &str, extension: &str) -> String {
+	format!("attachment; filename=\"{name}.{extension}\"")
+}
+
+async fn star_group_export_json(
+	State(pool): State<DbPool>,
+	Path(group): Path<String>,
+	Query(query): Query<ExportQuery>,
+	headers: HeaderMap,
+) -> Result<Response> {
+	let (group, posts) = load_export(&pool, group, &query, &headers).await?;
+	let json = export::to_json(&group, &posts)?;
+	Ok((
+		[
+			(header::CONTENT_TYPE, "application/json".to_owned()),
+			(header::CONTENT_DISPOSITION, attachment(&group.name, "json")),
+		],
+		json,
+	)
+		.into_response())
+}
+
+async fn star_group_export_csv(
+	State(pool): State<DbPool>,
+	Path(group): Path<String>,
+	Query(query): Query<ExportQuery>,
+	headers: HeaderMap,
+) -> Result<Response> {
+	let (group, posts) = load_export(&pool, group, &query, &headers).await?;
+	Ok((
+		[
+			(header::CONTENT_TYPE, "text/csv; charset=utf-8".to_owned()),
+			(header::CONTENT_DISPOSITION, attachment(&group.name, "csv")),
+		],
+		export::to_csv(&posts),
+	)
+		.into_response())
+}
+
+async fn star_group_export_html(
+	State(pool): State<DbPool>,
+	Path(group): Path<String>,
+	Query(query): Query<ExportQuery>,
+	headers: HeaderMap,
+) -> Result<Response> {
+	let (group, posts) = load_export(&pool, group, &query, &headers).await?;
+	let name = group.name.clone();
+	// the thumbnails are downloaded
+	let html = tokio::task::spawn_blocking(move || export::to_html(&group, &posts))
+		.await
+		.unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
+		.map_err(|e| -> Box<dyn Error> { e })?;
+	Ok((
+		[
+			(header::CONTENT_TYPE, "text/html; charset=utf-8".to_owned()),
+			(header::CONTENT_DISPOSITION, attachment(&name, "html")),
+		],
+		html,
+	)
+		.into_response())
+}
+
 fn gen_petname() -> String {
 	let pn = Petnames::default();
 	pn.generate_raw(&mut rand::thread_rng(), 3)
@@ -118,35 +512,560 @@ fn gen_petname() -> String {
 		.join("")
 }
 
-async fn star_group_submit(Path((mut group, id)): Path<(String, String)>) -> Result<String> {
-	if group == "new" {
-		group = gen_petname();
+/// Header carrying the edit token of the star list being modified.
+const EDIT_TOKEN: &str = "x-edit-token";
+/// Header carrying the edit token of the star list a post is moved to.
+const TARGET_TOKEN: &str = "x-target-token";
+/// Cookie carrying the edit tokens the browser knows (separated by dots), set by the pages.
+const TOKENS_COOKIE: &str = "startokens";
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+	headers.get(name).and_then(|x| x.to_str().ok())
+}
+
+/// Edit tokens the browser knows, whose star lists are searched and shown in the view of all stars.
+/// They are sent in a cookie rather than the query string, to keep them out of URLs.
+fn known_tokens(headers: &HeaderMap) -> Vec<String> {
+	headers
+		.get_all(header::COOKIE)
+		.iter()
+		.filter_map(|x| x.to_str().ok())
+		.flat_map(|x| x.split(';'))
+		.filter_map(|x| x.trim().strip_prefix(TOKENS_COOKIE))
+		.filter_map(|x| x.strip_prefix('='))
+		.flat_map(|x| x.split('.'))
+		.filter(|x| !x.is_empty())
+		.map(str::to_owned)
+		.collect()
+}
+
+/// Create a new star list with a random name. Returns its name and edit token.
+/// Unlisted star lists get an unguessable name.
+fn create_group(tx: &Transaction, visibility: Visibility) -> std::result::Result<(String, String), DbError> {
+	let gen_name = match visibility {
+		Visibility::Unlisted => gen_token,
+		Visibility::Public | Visibility::Private => gen_petname,
+	};
+	let mut group = gen_name();
+	while tx.group_exists(&group)? {
+		group = gen_name();
+	}
+	let token = tx.create_group(&group, visibility)?;
+	Ok((group, token))
+}
+
+/// Response to modifications of star lists.
+#[derive(Serialize)]
+struct GroupResponse {
+	group: String,
+	/// Only set if the star list was just created.
+	token: Option<String>,
+}
+
+async fn star_group_submit(
+	State(pool): State<DbPool>,
+	Path((group, id)): Path<(String, String)>,
+	Query(query): Query<StarQuery>,
+	headers: HeaderMap,
+) -> Result<Json<GroupResponse>> {
+	if group != "new" {
+		// check the token before asking Reddit, it is checked again when the post is added
+		let (group, headers) = (group.clone(), headers.clone());
+		pool.run(move |db| {
+			db.transaction()?
+				.check_token(&group, header_value(&headers, EDIT_TOKEN), Permission::Add)
+		})
+		.await?;
+	}
+	let post = reddit::get_post(&id).await?;
+	let info = StarInfo {
+		starred_at: None,
+		source_sub: query.sub,
+		source_sort: query
+			.sort
+			.map(|x| x.parse::<Sort>())
+			.transpose()?
+			.map(|x| x.id().to_owned()),
+		source_time: query
+			.time
+			.map(|x| x.parse::<Time>())
+			.transpose()?
+			.map(|x| x.id().to_owned()),
+		note: query.note.filter(|x| !x.is_empty()),
+		removed: false,
+		tags: vec![],
+		starred_by: None,
+	};
+	let visibility = query
+		.visibility
+		.map(|x| x.parse::<Visibility>())
+		.transpose()?
+		.unwrap_or(Visibility::Public);
+	let starred = post.clone();
+	let res = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			let (res, member) = if group == "new" {
+				let (group, token) = create_group(&tx, visibility)?;
+				let res = GroupResponse {
+					group,
+					token: Some(token),
+				};
+				(res, None)
+			} else {
+				let member = tx.check_token(&group, header_value(&headers, EDIT_TOKEN), Permission::Add)?;
+				(GroupResponse { group, token: None }, member)
+			};
+			let info = StarInfo {
+				starred_by: member,
+				..info
+			};
+			tx.put_post_in_group(&res.group, starred, &info)?;
+			tx.commit()?;
+			Ok(res)
+		})
+		.await?;
+	archive::queue(post);
+	Ok(Json(res))
+}
+
+async fn star_group_remove(
+	State(pool): State<DbPool>,
+	Path((group, id)): Path<(String, String)>,
+	headers: HeaderMap,
+) -> Result<()> {
+	let found = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_token(&group, header_value(&headers, EDIT_TOKEN), Permission::AddRemove)?;
+			let found = tx.remove_post_from_group(&group, &id)?;
+			tx.commit()?;
+			Ok(found)
+		})
+		.await?;
+	if !found {
+		return Err(DbError::NotFound("post not in star list").into());
 	}
-	let Some(post) = ({ POST_CACHE.read().await.get(&id).cloned() }) else {
-		return Err(StringError("failed to find post in cache, try reloading").into());
+	Ok(())
+}
+
+#[derive(Serialize)]
+struct ImportResponse {
+	group: String,
+	/// Only set if the star list was just created.
+	token: Option<String>,
+	imported: usize,
+	/// Input that is not a post, or posts that were not found
+	failed: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+	/// Visibility of newly created star lists
+	visibility: Option<String>,
+}
+
+/// Import posts (see [`import::parse`] for the accepted formats) into the star list.
+async fn star_group_import(
+	State(pool): State<DbPool>,
+	Path(group): Path<String>,
+	Query(query): Query<ImportQuery>,
+	headers: HeaderMap,
+	body: String,
+) -> Result<Json<ImportResponse>> {
+	let visibility = query
+		.visibility
+		.map(|x| x.parse::<Visibility>())
+		.transpose()?
+		.unwrap_or(Visibility::Public);
+	let edit_token = header_value(&headers, EDIT_TOKEN).map(str::to_owned);
+	if group != "new" {
+		let (group, edit_token) = (group.clone(), edit_token.clone());
+		pool.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_token(&group, edit_token.as_deref(), Permission::Add)
+		})
+		.await?;
+	}
+	let mut list = import::parse(&body);
+	let mut failed = list.unrecognized;
+	failed.extend(import::resolve(&mut list.items).await?);
+	let posts = list.items.iter().filter_map(|x| x.post.clone()).collect::<Vec<_>>();
+	let res = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			let (group, token, member) = if group == "new" {
+				let (group, token) = create_group(&tx, visibility)?;
+				(group, Some(token), None)
+			} else {
+				let member = tx.check_token(&group, edit_token.as_deref(), Permission::Add)?;
+				(group, None, member)
+			};
+			let imported = import::insert(&tx, &group, list.items, member)?;
+			tx.commit()?;
+			Ok(ImportResponse {
+				group,
+				token,
+				imported,
+				failed,
+			})
+		})
+		.await?;
+	for post in posts {
+		archive::queue(post);
+	}
+	Ok(Json(res))
+}
+
+/// Fetch the data of all posts in the star list again.
+async fn star_group_refresh(
+	State(pool): State<DbPool>,
+	Path(group): Path<String>,
+	headers: HeaderMap,
+) -> Result<Json<RefreshResult>> {
+	let ids = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_token(&group, header_value(&headers, EDIT_TOKEN), Permission::Add)?;
+			let posts = tx.get_posts_in_group(&group, StarOrder::Position)?;
+			Ok(posts.into_iter().map(|x| x.post.id).collect::<Vec<_>>())
+		})
+		.await?;
+	let mut res = RefreshResult::default();
+	for ids in ids.chunks(reddit::BY_ID_LIMIT) {
+		let ids = ids.to_vec();
+		let (tx, rx) = oneshot::channel();
+		reddit::get_posts_by_id(ids.clone(), tx);
+		let json = rx.await.map_err(|_| std::io::Error::other("Reddit worker stopped"))??;
+		reddit::cache_posts(json.posts()).await;
+		res = pool
+			.run(move |db| {
+				let tx = db.transaction()?;
+				refresh::apply(&tx, &ids, &json, &mut res)?;
+				tx.commit()?;
+				Ok(res)
+			})
+			.await?;
+	}
+	Ok(Json(res))
+}
+
+async fn star_group_move(
+	State(pool): State<DbPool>,
+	Path((group, id, target)): Path<(String, String, String)>,
+	headers: HeaderMap,
+) -> Result<Json<GroupResponse>> {
+	let res = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_token(&group, header_value(&headers, EDIT_TOKEN), Permission::AddRemove)?;
+			let res = if target == "new" {
+				let visibility = tx
+					.get_group(&group)?
+					.map(|x| x.visibility)
+					.unwrap_or(Visibility::Public);
+				let (group, token) = create_group(&tx, visibility)?;
+				GroupResponse {
+					group,
+					token: Some(token),
+				}
+			} else {
+				tx.check_token(&target, header_value(&headers, TARGET_TOKEN), Permission::Add)?;
+				GroupResponse {
+					group: target,
+					token: None,
+				}
+			};
+			if !tx.move_post_to_group(&group, &id, &res.group)? {
+				return Ok(None);
+			}
+			tx.commit()?;
+			Ok(Some(res))
+		})
+		.await?;
+	let Some(res) = res else {
+		return Err(DbError::NotFound("post not in star list").into());
 	};
-	with_db!(|db: &mut DB| {
+	Ok(Json(res))
+}
+
+async fn star_group_reorder(
+	State(pool): State<DbPool>,
+	Path((group, id, position)): Path<(String, String, usize)>,
+	headers: HeaderMap,
+) -> Result<()> {
+	let found = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_token(&group, header_value(&headers, EDIT_TOKEN), Permission::AddRemove)?;
+			let found = tx.set_post_position(&group, &id, position)?;
+			tx.commit()?;
+			Ok(found)
+		})
+		.await?;
+	if !found {
+		return Err(DbError::NotFound("post not in star list").into());
+	}
+	Ok(())
+}
+
+async fn star_group_tag(
+	State(pool): State<DbPool>,
+	Path((group, id, tag)): Path<(String, String, String)>,
+	headers: HeaderMap,
+) -> Result<()> {
+	let tag = normalize_tag(&tag)?;
+	let found = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_token(&group, header_value(&headers, EDIT_TOKEN), Permission::Add)?;
+			let found = tx.add_tag(&group, &id, &tag)?;
+			tx.commit()?;
+			Ok(found)
+		})
+		.await?;
+	if !found {
+		return Err(DbError::NotFound("post not in star list").into());
+	}
+	Ok(())
+}
+
+async fn star_group_untag(
+	State(pool): State<DbPool>,
+	Path((group, id, tag)): Path<(String, String, String)>,
+	headers: HeaderMap,
+) -> Result<()> {
+	let tag = normalize_tag(&tag)?;
+	let found = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_token(&group, header_value(&headers, EDIT_TOKEN), Permission::AddRemove)?;
+			let found = tx.remove_tag(&group, &id, &tag)?;
+			tx.commit()?;
+			Ok(found)
+		})
+		.await?;
+	if !found {
+		return Err(DbError::NotFound("post does not have this tag").into());
+	}
+	Ok(())
+}
+
+/// Unlisted star lists are renamed to an unguessable name, which is returned.
+async fn star_group_visibility(
+	State(pool): State<DbPool>,
+	Path((group, visibility)): Path<(String, String)>,
+	headers: HeaderMap,
+) -> Result<Json<GroupResponse>> {
+	let visibility = visibility.parse::<Visibility>()?;
+	let group = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_edit_token(&group, header_value(&headers, EDIT_TOKEN))?;
+			let current = tx.get_group(&group)?.map(|x| x.visibility);
+			let group = if visibility == Visibility::Unlisted && current != Some(Visibility::Unlisted) {
+				tx.unlist_group(&group)?
+			} else {
+				tx.set_group_visibility(&group, visibility)?;
+				group
+			};
+			tx.commit()?;
+			Ok(group)
+		})
+		.await?;
+	Ok(Json(GroupResponse { group, token: None }))
+}
+
+/// Names that cannot be used for star lists.
+const RESERVED_GROUP_NAMES: &[&str] = &["new", "all"];
+
+fn check_group_name(name: &str) -> std::result::Result<(), StringError> {
+	if name.is_empty() || name.len() > 64 {
+		return Err(StringError("star list name must have 1 to 64 characters"));
+	}
+	if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+		return Err(StringError("star list name may only contain letters, digits, - and _"));
+	}
+	if RESERVED_GROUP_NAMES.contains(&name) {
+		return Err(StringError("star list name is reserved"));
+	}
+	Ok(())
+}
+
+async fn star_group_rename(
+	State(pool): State<DbPool>,
+	Path((group, new_name)): Path<(String, String)>,
+	headers: HeaderMap,
+) -> Result<Json<GroupResponse>> {
+	check_group_name(&new_name)?;
+	let new_name = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_edit_token(&group, header_value(&headers, EDIT_TOKEN))?;
+			if tx
+				.get_group(&group)?
+				.is_some_and(|x| x.visibility == Visibility::Unlisted)
+			{
+				return Err(DbError::Forbidden("unlisted star lists keep their unguessable name"));
+			}
+			tx.rename_group(&group, &new_name)?;
+			tx.commit()?;
+			Ok(new_name)
+		})
+		.await?;
+	Ok(Json(GroupResponse {
+		group: new_name,
+		token: None,
+	}))
+}
+
+#[derive(Deserialize)]
+struct GroupDescription {
+	title: Option<String>,
+	description: Option<String>,
+}
+
+async fn star_group_describe(
+	State(pool): State<DbPool>,
+	Path(group): Path<String>,
+	headers: HeaderMap,
+	Json(details): Json<GroupDescription>,
+) -> Result<()> {
+	let title = details.title.filter(|x| !x.is_empty());
+	let description = details.description.filter(|x| !x.is_empty());
+	pool.run(move |db| {
 		let tx = db.transaction()?;
-		tx.put_post_in_group(&group, post)?;
+		tx.check_edit_token(&group, header_value(&headers, EDIT_TOKEN))?;
+		tx.set_group_description(&group, title.as_deref(), description.as_deref())?;
 		tx.commit()?;
 		Ok(())
-	})?;
-	Ok(group)
+	})
+	.await?;
+	Ok(())
 }
 
-async fn render_json(Query(q): Query<Q2>, Json(payload): Json<RedditData>) -> Result<Html<String>> {
-	{
-		let mut cache = POST_CACHE.write().await;
-		for x in payload.posts() {
-			cache.insert(x.id.clone(), x.clone());
+async fn star_group_delete(State(pool): State<DbPool>, Path(group): Path<String>, headers: HeaderMap) -> Result<()> {
+	pool.run(move |db| {
+		let tx = db.transaction()?;
+		tx.check_edit_token(&group, header_value(&headers, EDIT_TOKEN))?;
+		tx.delete_group(&group)?;
+		tx.commit()?;
+		Ok(())
+	})
+	.await?;
+	Ok(())
+}
+
+#[derive(Serialize)]
+struct MemberInfo {
+	name: String,
+	token: String,
+	can_remove: bool,
+	created_at: Option<i64>,
+}
+
+impl From<Member> for MemberInfo {
+	fn from(member: Member) -> Self {
+		MemberInfo {
+			name: member.name,
+			token: member.token,
+			can_remove: member.permission >= Permission::AddRemove,
+			created_at: member.created_at,
 		}
 	}
+}
+
+#[derive(Deserialize)]
+struct MemberQuery {
+	/// Allow the member to remove and reorder posts, not just add them
+	can_remove: Option<bool>,
+}
+
+fn check_member_name(name: &str) -> std::result::Result<(), StringError> {
+	if name.is_empty() || name.chars().count() > 32 {
+		return Err(StringError("member name must have 1 to 32 characters"));
+	}
+	if !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+		return Err(StringError("member name may only contain letters, digits, - and _"));
+	}
+	Ok(())
+}
+
+/// Members of the star list with their tokens, only visible to the owner.
+async fn star_group_members(
+	State(pool): State<DbPool>,
+	Path(group): Path<String>,
+	headers: HeaderMap,
+) -> Result<Json<Vec<MemberInfo>>> {
+	let members = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_edit_token(&group, header_value(&headers, EDIT_TOKEN))?;
+			tx.get_members(&group)
+		})
+		.await?;
+	Ok(Json(members.into_iter().map(MemberInfo::from).collect()))
+}
+
+async fn star_group_add_member(
+	State(pool): State<DbPool>,
+	Path((group, name)): Path<(String, String)>,
+	Query(query): Query<MemberQuery>,
+	headers: HeaderMap,
+) -> Result<Json<MemberInfo>> {
+	check_member_name(&name)?;
+	let permission = if query.can_remove.unwrap_or(false) {
+		Permission::AddRemove
+	} else {
+		Permission::Add
+	};
+	let (name, token) = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_edit_token(&group, header_value(&headers, EDIT_TOKEN))?;
+			let token = tx.add_member(&group, &name, permission)?;
+			tx.commit()?;
+			Ok((name, token))
+		})
+		.await?;
+	Ok(Json(MemberInfo::from(Member {
+		name,
+		token,
+		permission,
+		created_at: None,
+	})))
+}
+
+async fn star_group_remove_member(
+	State(pool): State<DbPool>,
+	Path((group, name)): Path<(String, String)>,
+	headers: HeaderMap,
+) -> Result<()> {
+	let found = pool
+		.run(move |db| {
+			let tx = db.transaction()?;
+			tx.check_edit_token(&group, header_value(&headers, EDIT_TOKEN))?;
+			let found = tx.remove_member(&group, &name)?;
+			tx.commit()?;
+			Ok(found)
+		})
+		.await?;
+	if !found {
+		return Err(DbError::NotFound("member not found").into());
+	}
+	Ok(())
+}
+
+async fn render_json(Query(q): Query<Q2>, Json(payload): Json<RedditData>) -> Result<Html<String>> {
+	reddit::cache_posts(payload.posts()).await;
 	Ok(Html(
 		template::get(TemplateParameters::render_grid_items(
 			Some(q.sub),
 			Some(q.sort.parse()?),
 			Some(q.time.parse()?),
 			q.autoplay,
+			q.layout.parse()?,
+			q.cols,
 			Some(payload),
 		))
 		.await?,
@@ -158,7 +1077,7 @@ async fn log_time(ClientIp(ip): ClientIp, RawQuery(query): RawQuery, req: Reques
 
 	let method = req.method().to_string();
 	let path = req.uri().path().to_owned();
-	let q = query.as_deref().unwrap_or_default();
+	let q = redact_token(query.as_deref().unwrap_or_default());
 	let q_mark = if !q.is_empty() { "?" } else { "" };
 
 	let res = next.run(req).await;
@@ -175,6 +1094,14 @@ async fn log_time(ClientIp(ip): ClientIp, RawQuery(query): RawQuery, req: Reques
 	res
 }
 
+/// Hide secret tokens in query strings.
+fn redact_token(query: &str) -> String {
+	query
+		.split('&')
+		.map(|x| if x.starts_with("token=") { "token=…" } else { x })
+		.join("&")
+}
+
 async fn favicon() -> impl IntoResponse {
 	(
 		StatusCode::OK,
@@ -191,6 +1118,77 @@ async fn hls_js() -> impl IntoResponse {
 	)
 }
 
+/// Archived media of starred posts.
+/// Supports single byte ranges, as used by hls.js for segments and by browsers when seeking.
+async fn archived_media(Path((id, file)): Path<(String, String)>, headers: HeaderMap) -> Result<Response> {
+	let Some(path) = archive::archived_file(&id, &file) else {
+		return Ok(StatusCode::NOT_FOUND.into_response());
+	};
+	let mut file_handle = tokio::fs::File::open(path).await?;
+	let len = file_handle.metadata().await?.len();
+	let range = header_value(&headers, header::RANGE.as_str()).and_then(|x| byte_range(x, len));
+	let (status, start, end) = match range {
+		Some((start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+		None => (StatusCode::OK, 0, len),
+	};
+	file_handle.seek(SeekFrom::Start(start)).await?;
+	let mut response_headers = HeaderMap::new();
+	response_headers.insert(
+		header::CONTENT_TYPE,
+		HeaderValue::from_static(archive::content_type(&file)),
+	);
+	response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(end - start));
+	response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+	response_headers.insert(
+		header::CACHE_CONTROL,
+		HeaderValue::from_static("public, max-age=31536000, immutable"),
+	);
+	if range.is_some() {
+		let content_range = format!("bytes {start}-{}/{len}", end - 1);
+		response_headers.insert(header::CONTENT_RANGE, content_range.parse().unwrap());
+	}
+	Ok((
+		status,
+		response_headers,
+		Body::from_stream(ReaderStream::new(file_handle.take(end - start))),
+	)
+		.into_response())
+}
+
+/// Parse a `Range` header with a single range of bytes, e.g. "bytes=0-99" or "bytes=-100".
+/// Returns the start and (exclusive) end, or `None` if the range is not satisfiable.
+fn byte_range(range: &str, len: u64) -> Option<(u64, u64)> {
+	let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+	let (start, end) = match (start.trim(), end.trim()) {
+		("", suffix) => (len.saturating_sub(suffix.parse().ok()?), len),
+		(start, "") => (start.parse().ok()?, len),
+		(start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.saturating_add(1).min(len)),
+	};
+	(start < end).then_some((start, end))
+}
+
+/// Post cache statistics, in the Prometheus text format.
+async fn metrics() -> impl IntoResponse {
+	let (len, stats) = {
+		let cache = reddit::POST_CACHE.lock().await;
+		(cache.len(), cache.stats())
+	};
+	let body = format!(
+		"post_cache_size {len}
+post_cache_hits_total {}
+post_cache_misses_total {}
+post_cache_evictions_total {}
+post_cache_expirations_total {}
+",
+		stats.hits, stats.misses, stats.evictions, stats.expirations
+	);
+	(
+		StatusCode::OK,
+		[(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+		body,
+	)
+}
+
 #[axum::debug_handler]
 async fn root() -> Result<Html<String>> {
 	Ok(Html(template::get(TemplateParameters::landing_page()).await?))
@@ -203,6 +1201,8 @@ async fn root_with_sub(Path(sub): Path<String>, Query(query): Query<Q>) -> Resul
 			None,
 			None,
 			query.autoplay.unwrap_or(false),
+			query.layout()?,
+			query.columns(),
 		))
 		.await?,
 	))
@@ -213,7 +1213,7 @@ async fn root_with_sub_redirect(sub: Path<String>) -> Redirect {
 }
 
 async fn root_with_sub_sort(Path(sub_sort): Path<(String, String)>, Query(query): Query<Q>) -> Result<Html<String>> {
-	let time = query.t.map(|x| x.parse::<Time>());
+	let time = query.t.as_ref().map(|x| x.parse::<Time>());
 	let time = if let Some(time) = time { Some(time?) } else { None };
 	Ok(Html(
 		template::get(TemplateParameters::render_ui(
@@ -221,6 +1221,8 @@ async fn root_with_sub_sort(Path(sub_sort): Path<(String, String)>, Query(query)
 			Some(sub_sort.1.parse()?),
 			time,
 			query.autoplay.unwrap_or(false),
+			query.layout()?,
+			query.columns(),
 		))
 		.await?,
 	))
@@ -240,6 +1242,52 @@ async fn root_with_sub_sort_redirect(sub_sort: Path<(String, String)>, RawQuery(
 struct Q {
 	t: Option<String>,
 	autoplay: Option<bool>,
+	layout: Option<String>,
+	cols: Option<usize>,
+	order: Option<String>,
+	/// Only show posts with this tag
+	tag: Option<String>,
+	/// Edit token, required to view private star lists
+	token: Option<String>,
+	/// Search words
+	q: Option<String>,
+	/// Only show posts of this star list (in the view of all stars)
+	list: Option<String>,
+}
+
+impl Q {
+	fn order(&self) -> std::result::Result<StarOrder, &'static str> {
+		Ok(self
+			.order
+			.as_deref()
+			.map(str::parse)
+			.transpose()?
+			.unwrap_or(StarOrder::Position))
+	}
+
+	fn layout(&self) -> std::result::Result<Layout, &'static str> {
+		Ok(self
+			.layout
+			.as_deref()
+			.map(str::parse)
+			.transpose()?
+			.unwrap_or(Layout::Grid))
+	}
+
+	fn columns(&self) -> usize {
+		self.cols.unwrap_or(DEFAULT_COLUMNS)
+	}
+}
+
+/// Where a post was starred from.
+#[derive(Deserialize)]
+struct StarQuery {
+	sub: Option<String>,
+	sort: Option<String>,
+	time: Option<String>,
+	note: Option<String>,
+	/// Visibility of newly created star lists
+	visibility: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -248,43 +1296,156 @@ struct Q2 {
 	sort: String,
 	time: String,
 	autoplay: bool,
+	layout: String,
+	cols: usize,
 }
 
-pub struct AppError {
-	inner: Box<dyn Error>,
+/// Error of a request handler. The variant determines the HTTP status.
+#[derive(Debug)]
+pub enum AppError {
+	/// Invalid parameters, e.g. an unknown sort order
+	BadRequest(String),
+	/// The requested resource does not exist, e.g. a post Reddit does not know
+	NotFound(String),
+	Database(DbError),
+	Reddit(RedditError),
+	Internal(Box<dyn Error>),
 }
 
-macro_rules! impl_from {
-	($type:ty) => {
-		impl From<$type> for AppError {
-			fn from(value: $type) -> Self {
-				Self {
-					inner: Box::new(value),
-				}
-			}
-		}
-	};
+impl From<std::io::Error> for AppError {
+	fn from(value: std::io::Error) -> Self {
+		AppError::Internal(Box::new(value))
+	}
 }
-impl_from!(std::io::Error);
-impl_from!(StringError);
 
-impl From<Box<dyn Error>> for AppError {
-	fn from(value: Box<dyn Error>) -> Self {
-		Self { inner: value }
+impl From<StringError> for AppError {
+	fn from(value: StringError) -> Self {
+		AppError::BadRequest(value.0.to_owned())
 	}
 }
 
+/// Parse errors of parameters (see the `FromStr` implementations).
 impl From<&'static str> for AppError {
 	fn from(value: &'static str) -> Self {
-		Self::from(std::io::Error::other(value))
+		AppError::BadRequest(value.to_owned())
+	}
+}
+
+impl From<DbError> for AppError {
+	fn from(value: DbError) -> Self {
+		AppError::Database(value)
+	}
+}
+
+impl From<RedditError> for AppError {
+	fn from(value: RedditError) -> Self {
+		AppError::Reddit(value)
+	}
+}
+
+impl From<Box<dyn Error>> for AppError {
+	fn from(value: Box<dyn Error>) -> Self {
+		let value = match value.downcast::<RedditError>() {
+			Ok(e) => return AppError::Reddit(*e),
+			Err(value) => value,
+		};
+		match value.downcast::<DbError>() {
+			Ok(e) => AppError::Database(*e),
+			Err(value) => AppError::Internal(value),
+		}
+	}
+}
+
+impl From<anyhow::Error> for AppError {
+	fn from(value: anyhow::Error) -> Self {
+		let value = match value.downcast::<RedditError>() {
+			Ok(e) => return AppError::Reddit(e),
+			Err(value) => value,
+		};
+		let value = match value.downcast::<PostNotFound>() {
+			Ok(e) => return AppError::NotFound(e.to_string()),
+			Err(value) => value,
+		};
+		match value.downcast::<StringError>() {
+			Ok(e) => AppError::from(e),
+			Err(value) => AppError::Internal(value.into()),
+		}
+	}
+}
+
+impl AppError {
+	fn status(&self) -> StatusCode {
+		match self {
+			AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+			AppError::NotFound(_) => StatusCode::NOT_FOUND,
+			AppError::Database(DbError::NotConfigured) => StatusCode::SERVICE_UNAVAILABLE,
+			AppError::Database(DbError::NotFound(_)) => StatusCode::NOT_FOUND,
+			AppError::Database(DbError::Conflict(_)) => StatusCode::CONFLICT,
+			AppError::Database(DbError::Forbidden(_)) => StatusCode::FORBIDDEN,
+			AppError::Reddit(_) => StatusCode::BAD_GATEWAY,
+			AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+		}
+	}
+
+	/// Message shown to the user. Details of internal errors are only logged.
+	fn message(&self) -> String {
+		match self {
+			AppError::BadRequest(msg) | AppError::NotFound(msg) => msg.clone(),
+			AppError::Database(DbError::NotConfigured) => "Star lists are not available on this server.".to_owned(),
+			AppError::Database(e @ (DbError::NotFound(_) | DbError::Conflict(_) | DbError::Forbidden(_))) => {
+				e.to_string()
+			},
+			AppError::Database(_) => "The database could not handle this request.".to_owned(),
+			AppError::Reddit(e) => e.to_string(),
+			AppError::Internal(_) => "Something went wrong on the server.".to_owned(),
+		}
 	}
 }
 
 impl IntoResponse for AppError {
 	fn into_response(self) -> Response {
-		let msg = format!("{:?}", self.inner);
-		tracing::warn!("Internal Server Error {:?}", self.inner);
-		(StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+		let status = self.status();
+		let msg = self.message();
+		if status.is_server_error() {
+			tracing::warn!("{status} {self:?}");
+		}
+		let mut res = (status, msg.clone()).into_response();
+		res.extensions_mut().insert(ErrorMessage(msg));
+		res
+	}
+}
+
+/// Message of an [`AppError`], shown on the error page.
+#[derive(Clone)]
+struct ErrorMessage(String);
+
+/// Show errors as a page to browsers. Scripts (e.g. `fetch`) get the plain message.
+async fn error_page(req: Request, next: Next) -> Response {
+	let wants_html = header_value(req.headers(), header::ACCEPT.as_str()).is_some_and(|x| x.contains("text/html"));
+	let res = next.run(req).await;
+	let status = res.status();
+	if !wants_html || !(status.is_client_error() || status.is_server_error()) {
+		return res;
+	}
+	let (parts, body) = res.into_parts();
+	let msg = if let Some(ErrorMessage(msg)) = parts.extensions.get::<ErrorMessage>() {
+		msg.clone()
+	} else if header_value(&parts.headers, header::CONTENT_TYPE.as_str()).is_some_and(|x| x.starts_with("text/plain")) {
+		// rejections of the built-in extractors, e.g. invalid query parameters
+		match axum::body::to_bytes(body, 64 * 1024).await {
+			Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+			Err(_) => return status.into_response(),
+		}
+	} else {
+		return Response::from_parts(parts, body);
+	};
+	let msg = msg.as_str().uppercase_first();
+	match template::get_error_page(status.as_u16(), status.canonical_reason().unwrap_or_default(), &msg) {
+		Ok(html) => (status, Html(html)).into_response(),
+		Err(e) => {
+			tracing::warn!("failed to render error page: {e:?}");
+			(status, msg).into_response()
+		},
 	}
 }
 
@@ -297,5 +1458,5 @@ fn handle_panic(err: Box<dyn Any + Send + 'static>) -> Response {
 	} else {
 		msg += "Unknown panic message";
 	};
-	(StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+	AppError::Internal(msg.into()).into_response()
 }
@@ -0,0 +1,146 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Map with a maximum number of entries and a maximum entry age.
+/// If full, the least recently used entry is evicted.
+pub struct LruCache<K, V> {
+	capacity: usize,
+	max_age: Duration,
+	entries: HashMap<K, Entry<V>>,
+	/// Keys by last use, least recently used first.
+	order: BTreeMap<u64, K>,
+	tick: u64,
+	stats: CacheStats,
+}
+
+struct Entry<V> {
+	value: V,
+	inserted: Instant,
+	last_used: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+	pub hits: u64,
+	pub misses: u64,
+	/// Entries removed to make room for new entries.
+	pub evictions: u64,
+	/// Entries removed because they were too old.
+	pub expirations: u64,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruCache<K, V> {
+	pub fn new(capacity: usize, max_age: Duration) -> Self {
+		LruCache {
+			capacity,
+			max_age,
+			entries: HashMap::new(),
+			order: BTreeMap::new(),
+			tick: 0,
+			stats: CacheStats::default(),
+		}
+	}
+
+	pub fn get<Q: Eq + Hash + ?Sized>(&mut self, key: &Q) -> Option<V>
+	where
+		K: Borrow<Q>,
+	{
+		self.get_at(key, Instant::now())
+	}
+
+	fn get_at<Q: Eq + Hash + ?Sized>(&mut self, key: &Q, now: Instant) -> Option<V>
+	where
+		K: Borrow<Q>,
+	{
+		let Some(entry) = self.entries.get_mut(key) else {
+			self.stats.misses += 1;
+			return None;
+		};
+		if now.duration_since(entry.inserted) > self.max_age {
+			self.order.remove(&entry.last_used);
+			self.entries.remove(key);
+			self.stats.expirations += 1;
+			self.stats.misses += 1;
+			return None;
+		}
+		self.tick += 1;
+		let key = self.order.remove(&entry.last_used).expect("LRU order out of sync");
+		self.order.insert(self.tick, key);
+		entry.last_used = self.tick;
+		self.stats.hits += 1;
+		Some(entry.value.clone())
+	}
+
+	pub fn insert(&mut self, key: K, value: V) {
+		self.insert_at(key, value, Instant::now());
+	}
+
+	fn insert_at(&mut self, key: K, value: V, now: Instant) {
+		if self.capacity == 0 {
+			return;
+		}
+		self.tick += 1;
+		let entry = Entry {
+			value,
+			inserted: now,
+			last_used: self.tick,
+		};
+		if let Some(old) = self.entries.insert(key.clone(), entry) {
+			self.order.remove(&old.last_used);
+		}
+		self.order.insert(self.tick, key);
+		while self.entries.len() > self.capacity {
+			let Some((_, oldest)) = self.order.pop_first() else {
+				break;
+			};
+			self.entries.remove(&oldest);
+			self.stats.evictions += 1;
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub fn stats(&self) -> CacheStats {
+		self.stats
+	}
+}
+
+impl fmt::Display for CacheStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} hits, {} misses, {} evictions, {} expirations",
+			self.hits, self.misses, self.evictions, self.expirations
+		)
+	}
+}
+
+#[test]
+fn lru_cache_test() {
+	let start = Instant::now();
+	let mut cache = LruCache::new(2, Duration::from_secs(60));
+	cache.insert_at("a", 1, start);
+	cache.insert_at("b", 2, start);
+	assert_eq!(cache.get_at("a", start), Some(1));
+	// b is the least recently used entry now
+	cache.insert_at("c", 3, start);
+	assert_eq!(cache.get_at("b", start), None);
+	assert_eq!(cache.get_at("a", start), Some(1));
+	assert_eq!(cache.get_at("c", start), Some(3));
+	assert_eq!(cache.get_at("c", start + Duration::from_secs(61)), None);
+	assert_eq!(cache.len(), 1);
+	let stats = cache.stats();
+	assert_eq!(
+		(stats.hits, stats.misses, stats.evictions, stats.expirations),
+		(3, 2, 1, 1)
+	);
+}
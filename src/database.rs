@@ -1,12 +1,20 @@
-use std::{cell::RefCell, error::Error};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use rusqlite::{Connection, Transaction, params};
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior, ffi, params};
 
-use crate::{DATABASE_PATH, StringError, reddit::RedditDataPostData};
+use crate::{DATABASE_PATH, gen_token, reddit::RedditDataPostData};
 
 #[macro_export]
 macro_rules! extract_row {
 		($($t:ty)*) => {
+				#[allow(clippy::needless_question_mark)]
 				|_row| {
 						let mut _i = 0usize;
 						Ok(($(_row.get::<_, $t>({ _i += 1; _i - 1 })?),*))
@@ -14,8 +22,176 @@ macro_rules! extract_row {
 		};
 }
 
-thread_local! {
-	pub static DATABASE: RefCell<Option<DB>> = RefCell::new(None);
+/// Errors of database queries.
+#[derive(Debug)]
+pub enum DbError {
+	/// No database is configured, so the star feature is disabled.
+	NotConfigured,
+	/// The database was created by a newer version of this program.
+	SchemaTooNew,
+	/// The star list (or the post or member in it) does not exist.
+	NotFound(&'static str),
+	/// The change conflicts with existing data, e.g. the name is already taken.
+	Conflict(&'static str),
+	/// The token is missing or does not allow the change.
+	Forbidden(&'static str),
+	/// A stored row could not be parsed.
+	CorruptRow(String),
+	/// Any other SQLite error.
+	Sqlite(rusqlite::Error),
+}
+
+impl DbError {
+	fn corrupt_row(e: impl fmt::Display) -> Self {
+		DbError::CorruptRow(e.to_string())
+	}
+}
+
+impl fmt::Display for DbError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DbError::NotConfigured => write!(f, "database not configured"),
+			DbError::SchemaTooNew => write!(f, "database schema is newer than this program"),
+			DbError::NotFound(msg) | DbError::Conflict(msg) | DbError::Forbidden(msg) => write!(f, "{msg}"),
+			DbError::CorruptRow(e) => write!(f, "corrupt database row: {e}"),
+			DbError::Sqlite(e) => write!(f, "database error: {e}"),
+		}
+	}
+}
+
+impl Error for DbError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			DbError::Sqlite(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<rusqlite::Error> for DbError {
+	fn from(value: rusqlite::Error) -> Self {
+		match value.sqlite_error().map(|x| x.extended_code) {
+			Some(ffi::SQLITE_CONSTRAINT_PRIMARYKEY | ffi::SQLITE_CONSTRAINT_UNIQUE) => {
+				DbError::Conflict("already exists")
+			},
+			_ => DbError::Sqlite(value),
+		}
+	}
+}
+
+impl From<serde_json::Error> for DbError {
+	fn from(value: serde_json::Error) -> Self {
+		DbError::corrupt_row(value)
+	}
+}
+
+/// How long a connection waits for another connection's write lock before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum number of idle connections kept open by [`DbPool`].
+const MAX_IDLE_CONNECTIONS: usize = 8;
+
+/// Shared handle to the database, cheap to clone.
+/// Connections are opened on demand and reused afterwards.
+#[derive(Clone)]
+pub struct DbPool {
+	/// `None` if no database is configured.
+	inner: Option<Arc<PoolInner>>,
+}
+
+struct PoolInner {
+	path: String,
+	idle: Mutex<Vec<DB>>,
+}
+
+impl DbPool {
+	/// Pool for the configured database (see [`DATABASE_PATH`]).
+	/// Without a configured database, the pool is disabled and every query fails.
+	pub fn new() -> Result<Self, DbError> {
+		match &*DATABASE_PATH {
+			Some(db_path) => Self::open(db_path),
+			None => Ok(Self::disabled()),
+		}
+	}
+
+	/// Open (or create) the database at the given path and bring its schema up to date.
+	pub fn open(db_path: &str) -> Result<Self, DbError> {
+		let db = DB::open(db_path)?;
+		Ok(DbPool {
+			inner: Some(Arc::new(PoolInner {
+				path: db_path.to_owned(),
+				idle: Mutex::new(vec![db]),
+			})),
+		})
+	}
+
+	pub fn disabled() -> Self {
+		DbPool { inner: None }
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.inner.is_some()
+	}
+
+	/// Take a connection out of the pool. It is put back when dropped.
+	/// This blocks while opening new connections, see [`DbPool::run`] for async code.
+	pub fn get(&self) -> Result<PooledDB, DbError> {
+		let Some(pool) = &self.inner else {
+			return Err(DbError::NotConfigured);
+		};
+		let db = pool.idle.lock().unwrap().pop();
+		let db = match db {
+			Some(db) => db,
+			None => DB::connect(&pool.path)?,
+		};
+		Ok(PooledDB {
+			db: Some(db),
+			pool: Arc::clone(pool),
+		})
+	}
+
+	/// Run the queries on the blocking thread pool of tokio.
+	pub async fn run<T, F>(&self, f: F) -> Result<T, DbError>
+	where
+		T: Send + 'static,
+		F: FnOnce(&mut DB) -> Result<T, DbError> + Send + 'static,
+	{
+		let pool = self.clone();
+		let res = tokio::task::spawn_blocking(move || {
+			let mut db = pool.get()?;
+			f(&mut db)
+		})
+		.await;
+		res.unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
+	}
+}
+
+/// Connection borrowed from a [`DbPool`].
+pub struct PooledDB {
+	db: Option<DB>,
+	pool: Arc<PoolInner>,
+}
+
+impl Deref for PooledDB {
+	type Target = DB;
+
+	fn deref(&self) -> &DB {
+		self.db.as_ref().unwrap()
+	}
+}
+
+impl DerefMut for PooledDB {
+	fn deref_mut(&mut self) -> &mut DB {
+		self.db.as_mut().unwrap()
+	}
+}
+
+impl Drop for PooledDB {
+	fn drop(&mut self) {
+		let mut idle = self.pool.idle.lock().unwrap();
+		if idle.len() < MAX_IDLE_CONNECTIONS {
+			idle.extend(self.db.take());
+		}
+	}
 }
 
 pub struct DB {
@@ -23,72 +199,1212 @@ pub struct DB {
 }
 
 impl DB {
-	pub fn new() -> Result<Self, Box<dyn Error>> {
-		if let Some(db_path) = &*DATABASE_PATH {
-			let db = Connection::open(db_path);
-			match db {
-				Ok(db) => {
-					db.execute(
-						"CREATE TABLE IF NOT EXISTS stars(
-							group_name TEXT NOT NULL,
-							reddit_id TEXT NOT NULL,
-							reddit_data TEXT NOT NULL
-						) STRICT",
-						[],
-					)?;
-					Ok(DB { db })
-				},
-				Err(e) => {
-					tracing::warn!("failed to open database: {:?}", e);
-					Err(Box::new(e))
-				},
-			}
-		} else {
-			Err(Box::new(StringError("database not configured")))
-		}
+	/// Open (or create) the database at the given path and bring its schema up to date.
+	pub fn open(db_path: &str) -> Result<Self, DbError> {
+		let mut db = Self::connect(db_path)?;
+		migrate(&mut db.db)?;
+		Ok(db)
+	}
+
+	/// Open the database without checking its schema.
+	fn connect(db_path: &str) -> Result<Self, DbError> {
+		let db = Connection::open(db_path).inspect_err(|e| tracing::warn!("failed to open database: {:?}", e))?;
+		// readers do not block the writer (and vice versa) in WAL mode
+		db.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+		db.busy_timeout(BUSY_TIMEOUT)?;
+		Ok(DB { db })
 	}
 
-	pub fn transaction(&mut self) -> Result<Transaction, Box<dyn Error>> {
+	pub fn transaction(&mut self) -> Result<Transaction<'_>, DbError> {
 		Ok(self.db.transaction()?)
 	}
 }
 
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// Schema migrations, applied in order on startup.
+/// The schema version (`PRAGMA user_version`) is the number of applied migrations.
+/// Only ever append to this list.
+const MIGRATIONS: &[Migration] = &[
+	migration_stars,
+	migration_unique_stars,
+	migration_star_info,
+	migration_groups,
+	migration_group_visibility,
+	migration_group_details,
+	migration_refresh,
+	migration_tags,
+	migration_search,
+	migration_members,
+];
+
+/// Each migration runs in its own immediate transaction, so that connections opened
+/// concurrently (e.g. by background threads) do not run the same migration twice.
+fn migrate(db: &mut Connection) -> Result<(), DbError> {
+	loop {
+		let tx = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+		let version: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+		if version > MIGRATIONS.len() {
+			return Err(DbError::SchemaTooNew);
+		}
+		if version == MIGRATIONS.len() {
+			return Ok(());
+		}
+		MIGRATIONS[version](&tx)?;
+		tx.pragma_update(None, "user_version", version + 1)?;
+		tx.commit()?;
+		tracing::info!("migrated database to schema version {}", version + 1);
+	}
+}
+
+/// Initial schema.
+/// Databases created before migrations were introduced may already have the table, possibly without `position`.
+fn migration_stars(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute(
+		"CREATE TABLE IF NOT EXISTS stars(
+			group_name TEXT NOT NULL,
+			reddit_id TEXT NOT NULL,
+			reddit_data TEXT NOT NULL,
+			position INTEGER NOT NULL DEFAULT 0
+		) STRICT",
+		[],
+	)?;
+	let has_position: bool = tx.query_row(
+		"SELECT COUNT(*) > 0 FROM pragma_table_info('stars') WHERE name = 'position'",
+		[],
+		|row| row.get(0),
+	)?;
+	if !has_position {
+		tx.execute("ALTER TABLE stars ADD COLUMN position INTEGER NOT NULL DEFAULT 0", [])?;
+		tx.execute("UPDATE stars SET position = rowid", [])?;
+	}
+	Ok(())
+}
+
+/// Each post may be starred only once per group.
+fn migration_unique_stars(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute_batch(
+		"DELETE FROM stars WHERE rowid NOT IN (SELECT MIN(rowid) FROM stars GROUP BY group_name, reddit_id);
+		CREATE UNIQUE INDEX stars_group_post ON stars(group_name, reddit_id);",
+	)
+}
+
+/// When, where and why a post was starred.
+fn migration_star_info(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute_batch(
+		"ALTER TABLE stars ADD COLUMN starred_at INTEGER;
+		ALTER TABLE stars ADD COLUMN source_sub TEXT;
+		ALTER TABLE stars ADD COLUMN source_sort TEXT;
+		ALTER TABLE stars ADD COLUMN source_time TEXT;
+		ALTER TABLE stars ADD COLUMN note TEXT;",
+	)
+}
+
+/// Star lists with their secret edit token.
+/// Existing star lists get a new token, which is logged once.
+fn migration_groups(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute(
+		"CREATE TABLE groups(
+			name TEXT PRIMARY KEY,
+			edit_token TEXT NOT NULL,
+			created_at INTEGER
+		) STRICT",
+		[],
+	)?;
+	let mut query = tx.prepare("SELECT DISTINCT group_name FROM stars")?;
+	let names = query
+		.query_map([], extract_row!(String))?
+		.collect::<Result<Vec<_>, _>>()?;
+	let mut insert = tx.prepare("INSERT INTO groups (name, edit_token) VALUES (?, ?)")?;
+	for name in names {
+		let token = gen_token();
+		insert.execute(params![name, token])?;
+		tracing::info!("edit token for existing star list {name}: {token}");
+	}
+	Ok(())
+}
+
+fn migration_group_visibility(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute(
+		"ALTER TABLE groups ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public'",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Title and description of star lists, old names of renamed star lists.
+fn migration_group_details(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute_batch(
+		"ALTER TABLE groups ADD COLUMN title TEXT;
+		ALTER TABLE groups ADD COLUMN description TEXT;
+		CREATE TABLE group_aliases(
+			old_name TEXT PRIMARY KEY,
+			name TEXT NOT NULL
+		) STRICT;",
+	)
+}
+
+/// When the post data was last fetched again, whether the post was removed from Reddit since.
+fn migration_refresh(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute_batch(
+		"ALTER TABLE stars ADD COLUMN refreshed_at INTEGER;
+		ALTER TABLE stars ADD COLUMN removed INTEGER NOT NULL DEFAULT 0;",
+	)
+}
+
+/// Free-form tags of starred posts.
+fn migration_tags(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute_batch(
+		"CREATE TABLE tags(
+			group_name TEXT NOT NULL,
+			reddit_id TEXT NOT NULL,
+			tag TEXT NOT NULL,
+			PRIMARY KEY (group_name, reddit_id, tag)
+		) STRICT;
+		CREATE INDEX tags_group_tag ON tags(group_name, tag);",
+	)
+}
+
+/// Full-text index of starred posts, kept up to date by triggers.
+fn migration_search(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute_batch(
+		"CREATE VIRTUAL TABLE stars_search USING fts5(title, subreddit, author, note);
+		INSERT INTO stars_search (rowid, title, subreddit, author, note)
+			SELECT rowid, json_extract(reddit_data, '$.title'), json_extract(reddit_data, '$.subreddit'),
+				json_extract(reddit_data, '$.author'), note
+			FROM stars;
+		CREATE TRIGGER stars_search_insert AFTER INSERT ON stars BEGIN
+			INSERT INTO stars_search (rowid, title, subreddit, author, note)
+			VALUES (new.rowid, json_extract(new.reddit_data, '$.title'), json_extract(new.reddit_data, '$.subreddit'),
+				json_extract(new.reddit_data, '$.author'), new.note);
+		END;
+		CREATE TRIGGER stars_search_update AFTER UPDATE OF reddit_data, note ON stars BEGIN
+			UPDATE stars_search SET title = json_extract(new.reddit_data, '$.title'),
+				subreddit = json_extract(new.reddit_data, '$.subreddit'),
+				author = json_extract(new.reddit_data, '$.author'), note = new.note
+			WHERE rowid = new.rowid;
+		END;
+		CREATE TRIGGER stars_search_delete AFTER DELETE ON stars BEGIN
+			DELETE FROM stars_search WHERE rowid = old.rowid;
+		END;",
+	)
+}
+
+/// Members of star lists with their own tokens, who starred which post.
+fn migration_members(tx: &Transaction) -> rusqlite::Result<()> {
+	tx.execute_batch(
+		"CREATE TABLE members(
+			group_name TEXT NOT NULL,
+			name TEXT NOT NULL,
+			token TEXT NOT NULL UNIQUE,
+			can_remove INTEGER NOT NULL,
+			created_at INTEGER,
+			PRIMARY KEY (group_name, name)
+		) STRICT;
+		ALTER TABLE stars ADD COLUMN starred_by TEXT;",
+	)
+}
+
+/// Who can see a star list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+	/// Anyone
+	Public,
+	/// Anyone who knows the (unguessable) name, not listed anywhere
+	Unlisted,
+	/// Only with the edit token
+	Private,
+}
+
+impl Visibility {
+	pub fn id(&self) -> &'static str {
+		match self {
+			Visibility::Public => "public",
+			Visibility::Unlisted => "unlisted",
+			Visibility::Private => "private",
+		}
+	}
+}
+
+impl FromStr for Visibility {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		Ok(match s {
+			"public" => Visibility::Public,
+			"unlisted" => Visibility::Unlisted,
+			"private" => Visibility::Private,
+			_ => return Err("invalid visibility parameter"),
+		})
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Group {
+	pub name: String,
+	pub visibility: Visibility,
+	/// Unix timestamp, not available for star lists created before this was recorded
+	pub created_at: Option<i64>,
+	pub title: Option<String>,
+	pub description: Option<String>,
+}
+
+/// What a token allows to do with a star list. Higher levels include the lower ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+	/// View and add posts (members)
+	Add,
+	/// Also remove and reorder posts (members)
+	AddRemove,
+	/// Everything, including managing members (owner, with the edit token of the star list)
+	Owner,
+}
+
+/// Someone allowed to add posts to a star list, with their own token.
+#[derive(Debug, Clone)]
+pub struct Member {
+	pub name: String,
+	pub token: String,
+	/// Either [`Permission::Add`] or [`Permission::AddRemove`]
+	pub permission: Permission,
+	/// Unix timestamp
+	pub created_at: Option<i64>,
+}
+
+/// Overview of a star list.
+#[derive(Debug, Clone)]
+pub struct GroupSummary {
+	pub group: Group,
+	pub post_count: usize,
+	/// Unix timestamp of the last starred post (or creation)
+	pub last_updated: Option<i64>,
+	/// First post of the star list
+	pub cover: Option<RedditDataPostData>,
+}
+
+/// Order of posts in a star list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StarOrder {
+	/// Manual order
+	Position,
+	/// Most recently starred first
+	Starred,
+	/// Most recently posted first
+	Posted,
+}
+
+impl StarOrder {
+	pub fn id(&self) -> &'static str {
+		match self {
+			StarOrder::Position => "position",
+			StarOrder::Starred => "starred",
+			StarOrder::Posted => "posted",
+		}
+	}
+
+	fn sql(&self) -> &'static str {
+		match self {
+			StarOrder::Position => "position, rowid",
+			StarOrder::Starred => "starred_at DESC, rowid DESC",
+			StarOrder::Posted => "json_extract(reddit_data, '$.created_utc') DESC, rowid DESC",
+		}
+	}
+}
+
+impl FromStr for StarOrder {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		Ok(match s {
+			"position" => StarOrder::Position,
+			"starred" => StarOrder::Starred,
+			"posted" => StarOrder::Posted,
+			_ => return Err("invalid order parameter"),
+		})
+	}
+}
+
+/// Metadata of a starred post.
+/// Not available for posts starred before this was recorded.
+#[derive(Debug, Default, Clone)]
+pub struct StarInfo {
+	/// Unix timestamp, set by the database
+	pub starred_at: Option<i64>,
+	/// Listing the post was starred from
+	pub source_sub: Option<String>,
+	pub source_sort: Option<String>,
+	pub source_time: Option<String>,
+	pub note: Option<String>,
+	/// The post was removed from Reddit after it was starred
+	pub removed: bool,
+	/// Sorted alphabetically
+	pub tags: Vec<String>,
+	/// Member who starred the post, not set if it was the owner
+	pub starred_by: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StarredPost {
+	/// Star list the post is in
+	pub group: String,
+	pub post: RedditDataPostData,
+	pub info: StarInfo,
+}
+
+fn query_group_summaries(
+	tx: &Transaction,
+	filter: &str,
+	params: impl rusqlite::Params,
+) -> Result<Vec<GroupSummary>, DbError> {
+	let mut query = tx.prepare(&format!(
+		"SELECT g.name, g.visibility, g.created_at, g.title, g.description,
+			COUNT(s.reddit_id), COALESCE(MAX(s.starred_at), g.created_at) AS last_updated,
+			(SELECT reddit_data FROM stars WHERE group_name = g.name ORDER BY position, rowid LIMIT 1)
+		FROM groups g LEFT JOIN stars s ON s.group_name = g.name
+		{filter}
+		GROUP BY g.name
+		ORDER BY last_updated IS NULL, last_updated DESC, g.name"
+	))?;
+	let rows = query.query_map(
+		params,
+		extract_row!(String String Option<i64> Option<String> Option<String> usize Option<i64> Option<String>),
+	)?;
+	let mut groups = vec![];
+	for row in rows {
+		let (name, visibility, created_at, title, description, post_count, last_updated, cover) = row?;
+		groups.push(GroupSummary {
+			group: Group {
+				name,
+				visibility: visibility.parse().map_err(DbError::corrupt_row)?,
+				created_at,
+				title,
+				description,
+			},
+			post_count,
+			last_updated,
+			cover: cover.map(|x| serde_json::from_str(&x)).transpose()?,
+		});
+	}
+	Ok(groups)
+}
+
+/// Columns of `stars` (aliased `s`) expected by [`query_starred_posts`].
+const STARRED_POST_COLUMNS: &str =
+	"s.group_name, s.reddit_data, s.starred_at, s.source_sub, s.source_sort, s.source_time, s.note, s.removed, s.starred_by,
+	(SELECT json_group_array(tag) FROM (SELECT tag FROM tags t
+		WHERE t.group_name = s.group_name AND t.reddit_id = s.reddit_id ORDER BY tag))";
+
+fn query_starred_posts(
+	tx: &Transaction,
+	sql: &str,
+	params: impl rusqlite::Params,
+) -> Result<Vec<StarredPost>, DbError> {
+	let mut query = tx.prepare(sql)?;
+	let rows = query.query_map(
+		params,
+		extract_row!(String String Option<i64> Option<String> Option<String> Option<String> Option<String> bool Option<String> String),
+	)?;
+	let mut posts = vec![];
+	for row in rows {
+		let (group, data, starred_at, source_sub, source_sort, source_time, note, removed, starred_by, tags) = row?;
+		posts.push(StarredPost {
+			group,
+			post: serde_json::from_str(&data)?,
+			info: StarInfo {
+				starred_at,
+				source_sub,
+				source_sort,
+				source_time,
+				note,
+				removed,
+				tags: serde_json::from_str(&tags)?,
+				starred_by,
+			},
+		});
+	}
+	Ok(posts)
+}
+
+/// Turn user input into an FTS5 query matching posts that contain all words (or words starting with them).
+fn fts_query(input: &str) -> Option<String> {
+	let words: Vec<_> = input
+		.split_whitespace()
+		.map(|x| format!("\"{}\"*", x.replace('"', "\"\"")))
+		.collect();
+	(!words.is_empty()).then(|| words.join(" "))
+}
+
 pub trait CommonQueries {
-	fn get_posts_in_group(&self, group: &str) -> Result<Vec<RedditDataPostData>, Box<dyn Error>>;
-	fn put_post_in_group(&self, group: &str, post: RedditDataPostData) -> Result<(), Box<dyn Error>>;
+	fn group_exists(&self, group: &str) -> Result<bool, DbError>;
+	fn get_group(&self, group: &str) -> Result<Option<Group>, DbError>;
+	/// Create a new, empty group. Returns its edit token.
+	fn create_group(&self, group: &str, visibility: Visibility) -> Result<String, DbError>;
+	fn set_group_visibility(&self, group: &str, visibility: Visibility) -> Result<(), DbError>;
+	fn set_group_description(&self, group: &str, title: Option<&str>, description: Option<&str>)
+	-> Result<(), DbError>;
+	/// Rename the group. The old name is kept as an alias.
+	fn rename_group(&self, group: &str, new_name: &str) -> Result<(), DbError>;
+	/// Make the group unlisted and rename it to an unguessable name.
+	/// No alias is kept, so the old names do not lead to the new one. Returns the new name.
+	fn unlist_group(&self, group: &str) -> Result<String, DbError>;
+	/// Get the current name of a renamed group.
+	fn resolve_group_alias(&self, old_name: &str) -> Result<Option<String>, DbError>;
+	/// Delete the group, including all its posts.
+	fn delete_group(&self, group: &str) -> Result<(), DbError>;
+	/// All public groups, most recently updated first.
+	fn list_public_groups(&self) -> Result<Vec<GroupSummary>, DbError>;
+	fn get_group_summary(&self, group: &str) -> Result<Option<GroupSummary>, DbError>;
+	/// Fails unless the group exists and the token is its edit token.
+	fn check_edit_token(&self, group: &str, token: Option<&str>) -> Result<(), DbError>;
+	/// Fails unless the group exists and the token (edit token or member token) grants the permission.
+	/// Returns the name of the member, or `None` for the owner.
+	fn check_token(&self, group: &str, token: Option<&str>, needed: Permission) -> Result<Option<String>, DbError>;
+	/// Add a member to the group. Returns the new member token.
+	fn add_member(&self, group: &str, name: &str, permission: Permission) -> Result<String, DbError>;
+	/// Returns false if there was no such member. Posts starred by the member are kept.
+	fn remove_member(&self, group: &str, name: &str) -> Result<bool, DbError>;
+	fn get_members(&self, group: &str) -> Result<Vec<Member>, DbError>;
+	fn get_posts_in_group(&self, group: &str, order: StarOrder) -> Result<Vec<StarredPost>, DbError>;
+	/// Posts of the groups with one of the edit or member tokens (or of all public groups if there are none),
+	/// most recently starred first. Posts in several groups are returned once per group.
+	fn get_posts_in_groups(&self, tokens: &[String]) -> Result<Vec<StarredPost>, DbError>;
+	/// Starred posts matching the search words, best match first, each only once.
+	/// Only public star lists and those with one of the edit or member tokens are searched.
+	fn search_posts(&self, input: &str, tokens: &[String]) -> Result<Vec<StarredPost>, DbError>;
+	/// All starred posts, each only once.
+	fn get_all_posts(&self) -> Result<Vec<RedditDataPostData>, DbError>;
+	/// IDs of starred posts not fetched from Reddit in the given number of seconds, least recently fetched first.
+	fn get_stale_posts(&self, max_age: u64, limit: usize) -> Result<Vec<String>, DbError>;
+	/// Store fresh data of a starred post (in all groups).
+	/// If the post was removed from Reddit, the old data is kept and the post is marked as removed.
+	fn update_post_data(&self, post: &RedditDataPostData) -> Result<(), DbError>;
+	/// Mark a starred post as removed from Reddit (in all groups), keeping its old data.
+	fn mark_post_removed(&self, id: &str) -> Result<(), DbError>;
+	/// Append the post to the end of the group.
+	/// If the post is already in the group, only its data is updated.
+	fn put_post_in_group(&self, group: &str, post: RedditDataPostData, info: &StarInfo) -> Result<(), DbError>;
+	/// Returns false if the post was not in the group.
+	fn remove_post_from_group(&self, group: &str, id: &str) -> Result<bool, DbError>;
+	/// Move the post to the end of another group.
+	/// If the other group already contains the post, it is just removed from this group.
+	/// Returns false if the post was not in the group.
+	fn move_post_to_group(&self, group: &str, id: &str, target: &str) -> Result<bool, DbError>;
+	/// Move the post to the given (zero-based) position within its group.
+	/// Returns false if the post was not in the group.
+	fn set_post_position(&self, group: &str, id: &str, position: usize) -> Result<bool, DbError>;
+	/// Returns false if the post was not in the group.
+	fn add_tag(&self, group: &str, id: &str, tag: &str) -> Result<bool, DbError>;
+	/// Returns false if the post did not have the tag.
+	fn remove_tag(&self, group: &str, id: &str, tag: &str) -> Result<bool, DbError>;
+	/// All tags used in the group with their number of posts, most used first.
+	fn get_group_tags(&self, group: &str) -> Result<Vec<(String, usize)>, DbError>;
 }
 
 impl<'conn> CommonQueries for Transaction<'conn> {
-	fn get_posts_in_group(&self, group: &str) -> Result<Vec<RedditDataPostData>, Box<dyn Error>> {
-		let mut query = self.prepare("SELECT reddit_data FROM stars WHERE group_name = ?")?;
-		let rows = query.query_map(params![group], extract_row!(String))?;
+	fn group_exists(&self, group: &str) -> Result<bool, DbError> {
+		let mut query = self.prepare("SELECT COUNT(*) > 0 FROM groups WHERE name = ?")?;
+		Ok(query.query_row(params![group], |row| row.get(0))?)
+	}
+
+	fn get_group(&self, group: &str) -> Result<Option<Group>, DbError> {
+		let mut query =
+			self.prepare("SELECT name, visibility, created_at, title, description FROM groups WHERE name = ?")?;
+		let row = query
+			.query_row(
+				params![group],
+				extract_row!(String String Option<i64> Option<String> Option<String>),
+			)
+			.optional()?;
+		let Some((name, visibility, created_at, title, description)) = row else {
+			return Ok(None);
+		};
+		Ok(Some(Group {
+			name,
+			visibility: visibility.parse::<Visibility>().map_err(DbError::corrupt_row)?,
+			created_at,
+			title,
+			description,
+		}))
+	}
+
+	fn create_group(&self, group: &str, visibility: Visibility) -> Result<String, DbError> {
+		let token = gen_token();
+		let mut query = self.prepare(
+			"INSERT INTO groups (name, edit_token, created_at, visibility)
+			VALUES (?, ?, CAST(strftime('%s', 'now') AS INTEGER), ?)",
+		)?;
+		query.execute(params![group, token, visibility.id()])?;
+		Ok(token)
+	}
+
+	fn set_group_visibility(&self, group: &str, visibility: Visibility) -> Result<(), DbError> {
+		let mut query = self.prepare("UPDATE groups SET visibility = ? WHERE name = ?")?;
+		query.execute(params![visibility.id(), group])?;
+		Ok(())
+	}
+
+	fn set_group_description(
+		&self,
+		group: &str,
+		title: Option<&str>,
+		description: Option<&str>,
+	) -> Result<(), DbError> {
+		let mut query = self.prepare("UPDATE groups SET title = ?, description = ? WHERE name = ?")?;
+		query.execute(params![title, description, group])?;
+		Ok(())
+	}
+
+	fn rename_group(&self, group: &str, new_name: &str) -> Result<(), DbError> {
+		if self.group_exists(new_name)? {
+			return Err(DbError::Conflict("star list already exists"));
+		}
+		self.execute("UPDATE groups SET name = ? WHERE name = ?", params![new_name, group])?;
+		self.execute("UPDATE stars SET group_name = ? WHERE group_name = ?", params![
+			new_name, group
+		])?;
+		self.execute("UPDATE tags SET group_name = ? WHERE group_name = ?", params![
+			new_name, group
+		])?;
+		self.execute("UPDATE members SET group_name = ? WHERE group_name = ?", params![
+			new_name, group
+		])?;
+		// the new name may have been an alias of another group
+		self.execute("DELETE FROM group_aliases WHERE old_name = ?", params![new_name])?;
+		self.execute("UPDATE group_aliases SET name = ? WHERE name = ?", params![
+			new_name, group
+		])?;
+		self.execute("INSERT INTO group_aliases (old_name, name) VALUES (?, ?)", params![
+			group, new_name
+		])?;
+		Ok(())
+	}
+
+	fn unlist_group(&self, group: &str) -> Result<String, DbError> {
+		let mut new_name = gen_token();
+		while self.group_exists(&new_name)? {
+			new_name = gen_token();
+		}
+		self.rename_group(group, &new_name)?;
+		self.execute("DELETE FROM group_aliases WHERE name = ?", params![new_name])?;
+		self.set_group_visibility(&new_name, Visibility::Unlisted)?;
+		Ok(new_name)
+	}
+
+	fn resolve_group_alias(&self, old_name: &str) -> Result<Option<String>, DbError> {
+		let mut query = self.prepare("SELECT name FROM group_aliases WHERE old_name = ?")?;
+		Ok(query.query_row(params![old_name], extract_row!(String)).optional()?)
+	}
+
+	fn delete_group(&self, group: &str) -> Result<(), DbError> {
+		self.execute("DELETE FROM stars WHERE group_name = ?", params![group])?;
+		self.execute("DELETE FROM tags WHERE group_name = ?", params![group])?;
+		self.execute("DELETE FROM members WHERE group_name = ?", params![group])?;
+		self.execute("DELETE FROM group_aliases WHERE name = ?", params![group])?;
+		self.execute("DELETE FROM groups WHERE name = ?", params![group])?;
+		Ok(())
+	}
+
+	fn list_public_groups(&self) -> Result<Vec<GroupSummary>, DbError> {
+		query_group_summaries(self, "WHERE g.visibility = 'public'", [])
+	}
+
+	fn get_group_summary(&self, group: &str) -> Result<Option<GroupSummary>, DbError> {
+		Ok(query_group_summaries(self, "WHERE g.name = ?", [group])?.pop())
+	}
+
+	fn check_edit_token(&self, group: &str, token: Option<&str>) -> Result<(), DbError> {
+		self.check_token(group, token, Permission::Owner)?;
+		Ok(())
+	}
+
+	fn check_token(&self, group: &str, token: Option<&str>, needed: Permission) -> Result<Option<String>, DbError> {
+		let mut query = self.prepare("SELECT edit_token FROM groups WHERE name = ?")?;
+		let expected = query.query_row(params![group], extract_row!(String)).optional()?;
+		let Some(expected) = expected else {
+			return Err(DbError::NotFound("star list not found"));
+		};
+		let Some(token) = token else {
+			return Err(DbError::Forbidden("edit token required"));
+		};
+		if token == expected {
+			return Ok(None);
+		}
+		let mut query = self.prepare("SELECT name, can_remove FROM members WHERE group_name = ? AND token = ?")?;
+		let member = query
+			.query_row(params![group, token], extract_row!(String bool))
+			.optional()?;
+		let Some((name, can_remove)) = member else {
+			return Err(DbError::Forbidden("invalid edit token"));
+		};
+		let permission = if can_remove {
+			Permission::AddRemove
+		} else {
+			Permission::Add
+		};
+		if permission < needed {
+			return Err(DbError::Forbidden("token does not allow this change"));
+		}
+		Ok(Some(name))
+	}
+
+	fn add_member(&self, group: &str, name: &str, permission: Permission) -> Result<String, DbError> {
+		let mut query = self.prepare("SELECT COUNT(*) > 0 FROM members WHERE group_name = ? AND name = ?")?;
+		if query.query_row(params![group, name], |row| row.get(0))? {
+			return Err(DbError::Conflict("member already exists"));
+		}
+		let token = gen_token();
+		self.execute(
+			"INSERT INTO members (group_name, name, token, can_remove, created_at)
+			VALUES (?, ?, ?, ?, CAST(strftime('%s', 'now') AS INTEGER))",
+			params![group, name, token, permission >= Permission::AddRemove],
+		)?;
+		Ok(token)
+	}
+
+	fn remove_member(&self, group: &str, name: &str) -> Result<bool, DbError> {
+		let mut query = self.prepare("DELETE FROM members WHERE group_name = ? AND name = ?")?;
+		Ok(query.execute(params![group, name])? > 0)
+	}
+
+	fn get_members(&self, group: &str) -> Result<Vec<Member>, DbError> {
+		let mut query = self.prepare(
+			"SELECT name, token, can_remove, created_at FROM members WHERE group_name = ? ORDER BY created_at, name",
+		)?;
+		let rows = query.query_map(params![group], extract_row!(String String bool Option<i64>))?;
+		let mut members = vec![];
+		for row in rows {
+			let (name, token, can_remove, created_at) = row?;
+			members.push(Member {
+				name,
+				token,
+				permission: if can_remove {
+					Permission::AddRemove
+				} else {
+					Permission::Add
+				},
+				created_at,
+			});
+		}
+		Ok(members)
+	}
+
+	fn get_all_posts(&self) -> Result<Vec<RedditDataPostData>, DbError> {
+		let mut query = self.prepare("SELECT reddit_data FROM stars GROUP BY reddit_id")?;
+		let rows = query.query_map([], extract_row!(String))?;
 		let mut posts = vec![];
 		for row in rows {
-			let row = row?;
-			let data = serde_json::from_str(&row);
-			posts.push(data?);
+			posts.push(serde_json::from_str(&row?)?);
 		}
 		Ok(posts)
 	}
 
-	fn put_post_in_group(&self, group: &str, post: RedditDataPostData) -> Result<(), Box<dyn Error>> {
-		let mut query = self.prepare("INSERT INTO stars (group_name, reddit_id, reddit_data) VALUES (?, ?, ?)")?;
-		query.execute(params![group, post.id.clone(), serde_json::to_string(&post)?])?;
+	fn get_stale_posts(&self, max_age: u64, limit: usize) -> Result<Vec<String>, DbError> {
+		let mut query = self.prepare(
+			"SELECT reddit_id FROM stars
+			WHERE removed = 0
+			GROUP BY reddit_id
+			HAVING MIN(COALESCE(refreshed_at, 0)) < CAST(strftime('%s', 'now') AS INTEGER) - ?
+			ORDER BY MIN(COALESCE(refreshed_at, 0))
+			LIMIT ?",
+		)?;
+		let rows = query.query_map(params![max_age, limit], extract_row!(String))?;
+		Ok(rows.collect::<Result<Vec<_>, _>>()?)
+	}
+
+	fn update_post_data(&self, post: &RedditDataPostData) -> Result<(), DbError> {
+		if post.removed_by_category.is_some() {
+			self.mark_post_removed(&post.id)?;
+		} else {
+			self.execute(
+				"UPDATE stars SET reddit_data = ?, refreshed_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE reddit_id = ?",
+				params![serde_json::to_string(post)?, post.id],
+			)?;
+		}
+		Ok(())
+	}
+
+	fn mark_post_removed(&self, id: &str) -> Result<(), DbError> {
+		self.execute(
+			"UPDATE stars SET removed = 1, refreshed_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE reddit_id = ?",
+			params![id],
+		)?;
 		Ok(())
 	}
+
+	fn get_posts_in_group(&self, group: &str, order: StarOrder) -> Result<Vec<StarredPost>, DbError> {
+		query_starred_posts(
+			self,
+			&format!(
+				"SELECT {STARRED_POST_COLUMNS} FROM stars s WHERE group_name = ? ORDER BY {}",
+				order.sql()
+			),
+			params![group],
+		)
+	}
+
+	fn get_posts_in_groups(&self, tokens: &[String]) -> Result<Vec<StarredPost>, DbError> {
+		query_starred_posts(
+			self,
+			&format!(
+				"SELECT {STARRED_POST_COLUMNS} FROM stars s
+				WHERE group_name IN (SELECT name FROM groups WHERE CASE
+					WHEN json_array_length(?1) = 0 THEN visibility = 'public'
+					ELSE edit_token IN (SELECT value FROM json_each(?1))
+						OR name IN (SELECT group_name FROM members WHERE token IN (SELECT value FROM json_each(?1))) END)
+				ORDER BY {}",
+				StarOrder::Starred.sql()
+			),
+			params![serde_json::to_string(tokens)?],
+		)
+	}
+
+	fn search_posts(&self, input: &str, tokens: &[String]) -> Result<Vec<StarredPost>, DbError> {
+		let Some(fts_query) = fts_query(input) else {
+			return Ok(vec![]);
+		};
+		let mut posts = query_starred_posts(
+			self,
+			&format!(
+				"SELECT {STARRED_POST_COLUMNS}
+				FROM stars_search f JOIN stars s ON s.rowid = f.rowid
+				WHERE stars_search MATCH ?1 AND s.group_name IN (SELECT name FROM groups
+					WHERE visibility = 'public' OR edit_token IN (SELECT value FROM json_each(?2))
+						OR name IN (SELECT group_name FROM members WHERE token IN (SELECT value FROM json_each(?2))))
+				ORDER BY f.rank"
+			),
+			params![fts_query, serde_json::to_string(tokens)?],
+		)?;
+		let mut seen = HashSet::new();
+		posts.retain(|x| seen.insert(x.post.id.clone()));
+		Ok(posts)
+	}
+
+	fn put_post_in_group(&self, group: &str, post: RedditDataPostData, info: &StarInfo) -> Result<(), DbError> {
+		let mut query = self.prepare(
+			"INSERT INTO stars (group_name, reddit_id, reddit_data, position,
+				starred_at, source_sub, source_sort, source_time, note, starred_by)
+			VALUES (?1, ?2, ?3, (SELECT COALESCE(MAX(position) + 1, 0) FROM stars WHERE group_name = ?1),
+				CAST(strftime('%s', 'now') AS INTEGER), ?4, ?5, ?6, ?7, ?8)
+			ON CONFLICT (group_name, reddit_id) DO UPDATE SET reddit_data = excluded.reddit_data",
+		)?;
+		query.execute(params![
+			group,
+			post.id.clone(),
+			serde_json::to_string(&post)?,
+			info.source_sub,
+			info.source_sort,
+			info.source_time,
+			info.note,
+			info.starred_by
+		])?;
+		for tag in &info.tags {
+			self.add_tag(group, &post.id, tag)?;
+		}
+		Ok(())
+	}
+
+	fn remove_post_from_group(&self, group: &str, id: &str) -> Result<bool, DbError> {
+		self.execute("DELETE FROM tags WHERE group_name = ? AND reddit_id = ?", params![
+			group, id
+		])?;
+		let mut query = self.prepare("DELETE FROM stars WHERE group_name = ? AND reddit_id = ?")?;
+		Ok(query.execute(params![group, id])? > 0)
+	}
+
+	fn move_post_to_group(&self, group: &str, id: &str, target: &str) -> Result<bool, DbError> {
+		// tags are merged with those the post already has in the other group
+		self.execute(
+			"UPDATE OR IGNORE tags SET group_name = ?3 WHERE group_name = ?1 AND reddit_id = ?2",
+			params![group, id, target],
+		)?;
+		let mut query = self.prepare(
+			"UPDATE OR IGNORE stars SET group_name = ?3,
+			position = (SELECT COALESCE(MAX(position) + 1, 0) FROM stars WHERE group_name = ?3)
+			WHERE group_name = ?1 AND reddit_id = ?2",
+		)?;
+		if query.execute(params![group, id, target])? > 0 {
+			self.execute("DELETE FROM tags WHERE group_name = ? AND reddit_id = ?", params![
+				group, id
+			])?;
+			return Ok(true);
+		}
+		self.remove_post_from_group(group, id)
+	}
+
+	fn set_post_position(&self, group: &str, id: &str, position: usize) -> Result<bool, DbError> {
+		let mut query = self.prepare("SELECT reddit_id FROM stars WHERE group_name = ? ORDER BY position, rowid")?;
+		let mut ids = query
+			.query_map(params![group], extract_row!(String))?
+			.collect::<Result<Vec<_>, _>>()?;
+		let Some(old_position) = ids.iter().position(|x| x == id) else {
+			return Ok(false);
+		};
+		let id = ids.remove(old_position);
+		ids.insert(position.min(ids.len()), id);
+		let mut update = self.prepare("UPDATE stars SET position = ? WHERE group_name = ? AND reddit_id = ?")?;
+		for (i, id) in ids.iter().enumerate() {
+			update.execute(params![i, group, id])?;
+		}
+		Ok(true)
+	}
+
+	fn add_tag(&self, group: &str, id: &str, tag: &str) -> Result<bool, DbError> {
+		let mut query = self.prepare("SELECT COUNT(*) > 0 FROM stars WHERE group_name = ? AND reddit_id = ?")?;
+		if !query.query_row(params![group, id], |row| row.get(0))? {
+			return Ok(false);
+		}
+		self.execute(
+			"INSERT OR IGNORE INTO tags (group_name, reddit_id, tag) VALUES (?, ?, ?)",
+			params![group, id, tag],
+		)?;
+		Ok(true)
+	}
+
+	fn remove_tag(&self, group: &str, id: &str, tag: &str) -> Result<bool, DbError> {
+		let mut query = self.prepare("DELETE FROM tags WHERE group_name = ? AND reddit_id = ? AND tag = ?")?;
+		Ok(query.execute(params![group, id, tag])? > 0)
+	}
+
+	fn get_group_tags(&self, group: &str) -> Result<Vec<(String, usize)>, DbError> {
+		let mut query = self.prepare(
+			"SELECT tag, COUNT(*) AS count FROM tags WHERE group_name = ? GROUP BY tag ORDER BY count DESC, tag",
+		)?;
+		let rows = query.query_map(params![group], extract_row!(String usize))?;
+		Ok(rows.collect::<Result<Vec<_>, _>>()?)
+	}
 }
 
-#[macro_export]
-macro_rules! with_db {
-	($code:expr) => {
-		reddit_image_grid::database::DATABASE.with(|db| {
-			let mut db = db.borrow_mut();
-			if db.is_none() {
-				*db = Some(DB::new()?);
-			}
-			let db: &mut DB = db.as_mut().unwrap();
-			let result: std::result::Result<_, Box<dyn std::error::Error>> = $code(db);
-			result
-		})
+#[cfg(test)]
+fn test_post(id: &str) -> RedditDataPostData {
+	serde_json::from_value(serde_json::json!({
+		"title": format!("Post {id}"),
+		"url": format!("https://i.redd.it/{id}.jpg"),
+		"subreddit": "pics",
+		"author": "someone",
+		"permalink": format!("/r/pics/comments/{id}/post/"),
+		"id": id,
+		"secure_media": null,
+		"preview": null,
+		"media_metadata": null,
+		"removed_by_category": null,
+	}))
+	.unwrap()
+}
+
+#[cfg(test)]
+fn schema_version(db: &Connection) -> usize {
+	db.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+}
+
+/// In-memory database with the current schema.
+#[cfg(test)]
+fn test_db() -> DB {
+	let mut db = Connection::open_in_memory().unwrap();
+	migrate(&mut db).unwrap();
+	DB { db }
+}
+
+#[test]
+fn migrate_new_database_test() {
+	let mut db = Connection::open_in_memory().unwrap();
+	migrate(&mut db).unwrap();
+	assert_eq!(schema_version(&db), MIGRATIONS.len());
+	// running again is a no-op
+	migrate(&mut db).unwrap();
+	assert_eq!(schema_version(&db), MIGRATIONS.len());
+}
+
+#[test]
+fn groups_test() {
+	let mut db = test_db();
+	let tx = db.transaction().unwrap();
+	let token = tx.create_group("Group", Visibility::Public).unwrap();
+	assert!(matches!(
+		tx.create_group("Group", Visibility::Private),
+		Err(DbError::Conflict(_))
+	));
+	assert!(tx.check_edit_token("Group", Some(&token)).is_ok());
+	assert!(matches!(
+		tx.check_edit_token("Group", Some("wrong")),
+		Err(DbError::Forbidden(_))
+	));
+	assert!(tx.check_edit_token("Group", None).is_err());
+	assert!(matches!(
+		tx.check_edit_token("Other", Some(&token)),
+		Err(DbError::NotFound(_))
+	));
+	let info = StarInfo {
+		note: Some("nice".to_owned()),
+		..Default::default()
+	};
+	tx.put_post_in_group("Group", test_post("a"), &info).unwrap();
+	tx.put_post_in_group("Group", test_post("b"), &StarInfo::default())
+		.unwrap();
+	tx.put_post_in_group("Group", test_post("a"), &StarInfo::default())
+		.unwrap();
+	let posts = tx.get_posts_in_group("Group", StarOrder::Position).unwrap();
+	assert_eq!(posts.iter().map(|x| &*x.post.id).collect::<Vec<_>>(), ["a", "b"]);
+	assert_eq!(posts[0].info.note.as_deref(), Some("nice"));
+	assert!(posts[0].info.starred_at.is_some());
+
+	let groups = tx.list_public_groups().unwrap();
+	assert_eq!(groups.len(), 1);
+	assert_eq!(groups[0].post_count, 2);
+	assert_eq!(groups[0].cover.as_ref().map(|x| &*x.id), Some("a"));
+	tx.create_group("Hidden", Visibility::Private).unwrap();
+	assert_eq!(tx.list_public_groups().unwrap().len(), 1);
+	let summary = tx.get_group_summary("Hidden").unwrap().unwrap();
+	assert_eq!(summary.group.visibility, Visibility::Private);
+	assert_eq!(summary.post_count, 0);
+
+	tx.delete_group("Group").unwrap();
+	assert!(tx.get_group("Group").unwrap().is_none());
+	assert!(tx.get_posts_in_group("Group", StarOrder::Position).unwrap().is_empty());
+}
+
+#[test]
+fn refresh_test() {
+	let mut db = test_db();
+	let tx = db.transaction().unwrap();
+	tx.create_group("Group", Visibility::Public).unwrap();
+	tx.put_post_in_group("Group", test_post("a"), &StarInfo::default())
+		.unwrap();
+	tx.put_post_in_group("Group", test_post("b"), &StarInfo::default())
+		.unwrap();
+	let mut removed = test_post("b");
+	removed.title = "Changed".to_owned();
+	removed.removed_by_category = Some("deleted".to_owned());
+	tx.update_post_data(&removed).unwrap();
+	let posts = tx.get_posts_in_group("Group", StarOrder::Position).unwrap();
+	assert!(!posts[0].info.removed);
+	assert!(posts[1].info.removed);
+	assert_eq!(posts[1].post.title, "Post b");
+	assert_eq!(tx.get_stale_posts(0, 10).unwrap(), ["a"]);
+	tx.update_post_data(&test_post("a")).unwrap();
+	assert!(tx.get_stale_posts(60, 10).unwrap().is_empty());
+}
+
+#[test]
+fn refresh_missing_post_test() {
+	let mut db = test_db();
+	let tx = db.transaction().unwrap();
+	tx.create_group("Group", Visibility::Public).unwrap();
+	for id in ["a", "b", "c"] {
+		tx.put_post_in_group("Group", test_post(id), &StarInfo::default())
+			.unwrap();
+	}
+	// Reddit does not return "b" anymore
+	let ids = ["a", "b"].map(str::to_owned);
+	let json = crate::reddit::RedditData::from_posts(vec![test_post("a")]);
+	let mut res = crate::refresh::RefreshResult::default();
+	crate::refresh::apply(&tx, &ids, &json, &mut res).unwrap();
+	assert_eq!((res.updated, res.removed), (2, 1));
+	let posts = tx.get_posts_in_group("Group", StarOrder::Position).unwrap();
+	assert!(!posts[0].info.removed);
+	assert!(posts[1].info.removed);
+	assert_eq!(tx.get_stale_posts(0, 10).unwrap(), ["c"]);
+}
+
+#[test]
+fn members_test() {
+	let mut db = test_db();
+	let tx = db.transaction().unwrap();
+	let token = tx.create_group("Group", Visibility::Public).unwrap();
+	let adder = tx.add_member("Group", "alice", Permission::Add).unwrap();
+	let remover = tx.add_member("Group", "bob", Permission::AddRemove).unwrap();
+	assert!(matches!(
+		tx.add_member("Group", "alice", Permission::AddRemove),
+		Err(DbError::Conflict(_))
+	));
+	assert_eq!(tx.check_token("Group", Some(&token), Permission::Owner).unwrap(), None);
+	assert_eq!(
+		tx.check_token("Group", Some(&adder), Permission::Add)
+			.unwrap()
+			.as_deref(),
+		Some("alice")
+	);
+	assert!(tx.check_token("Group", Some(&adder), Permission::AddRemove).is_err());
+	assert!(tx.check_token("Group", Some(&remover), Permission::AddRemove).is_ok());
+	assert!(tx.check_edit_token("Group", Some(&remover)).is_err());
+	assert!(tx.remove_member("Group", "bob").unwrap());
+	assert!(tx.check_token("Group", Some(&remover), Permission::Add).is_err());
+	assert_eq!(tx.get_members("Group").unwrap().len(), 1);
+
+	let info = StarInfo {
+		starred_by: Some("alice".to_owned()),
+		..Default::default()
+	};
+	tx.put_post_in_group("Group", test_post("a"), &info).unwrap();
+	let posts = tx.get_posts_in_group("Group", StarOrder::Position).unwrap();
+	assert_eq!(posts[0].info.starred_by.as_deref(), Some("alice"));
+}
+
+#[test]
+fn tags_test() {
+	let mut db = test_db();
+	let tx = db.transaction().unwrap();
+	tx.create_group("Group", Visibility::Public).unwrap();
+	tx.create_group("Other", Visibility::Public).unwrap();
+	tx.put_post_in_group("Group", test_post("a"), &StarInfo::default())
+		.unwrap();
+	tx.put_post_in_group("Group", test_post("b"), &StarInfo::default())
+		.unwrap();
+	assert!(tx.add_tag("Group", "a", "red").unwrap());
+	assert!(tx.add_tag("Group", "a", "blue").unwrap());
+	assert!(tx.add_tag("Group", "b", "red").unwrap());
+	assert!(tx.add_tag("Group", "a", "red").unwrap());
+	assert!(!tx.add_tag("Group", "c", "red").unwrap());
+	let posts = tx.get_posts_in_group("Group", StarOrder::Position).unwrap();
+	assert_eq!(posts[0].info.tags, ["blue", "red"]);
+	assert_eq!(tx.get_group_tags("Group").unwrap(), [
+		("red".to_owned(), 2),
+		("blue".to_owned(), 1)
+	]);
+	assert!(tx.remove_tag("Group", "b", "red").unwrap());
+	assert!(!tx.remove_tag("Group", "b", "red").unwrap());
+
+	// tags are merged when moving a post to a group that already contains it
+	tx.put_post_in_group("Other", test_post("a"), &StarInfo {
+		tags: vec!["red".to_owned(), "green".to_owned()],
+		..Default::default()
+	})
+	.unwrap();
+	assert!(tx.move_post_to_group("Group", "a", "Other").unwrap());
+	assert!(tx.get_group_tags("Group").unwrap().is_empty());
+	let posts = tx.get_posts_in_group("Other", StarOrder::Position).unwrap();
+	assert_eq!(posts[0].info.tags, ["blue", "green", "red"]);
+}
+
+#[test]
+fn search_test() {
+	let mut db = test_db();
+	let tx = db.transaction().unwrap();
+	let token = tx.create_group("Group", Visibility::Public).unwrap();
+	let info = StarInfo {
+		note: Some("nice".to_owned()),
+		..Default::default()
 	};
+	tx.put_post_in_group("Group", test_post("a"), &info).unwrap();
+	tx.put_post_in_group("Group", test_post("b"), &StarInfo::default())
+		.unwrap();
+	let ids = |posts: Vec<StarredPost>| posts.into_iter().map(|x| x.post.id).collect::<Vec<_>>();
+	assert_eq!(ids(tx.search_posts("nic", &[]).unwrap()), ["a"]);
+	assert_eq!(ids(tx.search_posts("PICS post", &[]).unwrap()).len(), 2);
+	assert!(tx.search_posts("\"unbalanced", &[]).unwrap().is_empty());
+	assert!(tx.search_posts("  ", &[]).unwrap().is_empty());
+
+	let hidden_token = tx.create_group("Secret", Visibility::Private).unwrap();
+	let mut secret = test_post("s");
+	secret.title = "Hidden treasure".to_owned();
+	tx.put_post_in_group("Secret", secret, &StarInfo::default()).unwrap();
+	assert!(tx.search_posts("treasure", &[]).unwrap().is_empty());
+	let tokens = [hidden_token, token];
+	assert_eq!(ids(tx.search_posts("treasure", &tokens[..1]).unwrap()), ["s"]);
+	assert_eq!(tx.get_posts_in_groups(&[]).unwrap().len(), 2);
+	let posts = tx.get_posts_in_groups(&tokens).unwrap();
+	assert_eq!(posts.len(), 3);
+	assert_eq!(posts.iter().filter(|x| x.group == "Secret").count(), 1);
+}
+
+#[test]
+fn rename_group_test() {
+	let mut db = test_db();
+	let tx = db.transaction().unwrap();
+	let token = tx.create_group("Group", Visibility::Public).unwrap();
+	tx.put_post_in_group("Group", test_post("a"), &StarInfo::default())
+		.unwrap();
+	assert!(tx.add_tag("Group", "a", "red").unwrap());
+	tx.rename_group("Group", "Renamed").unwrap();
+	tx.rename_group("Renamed", "Again").unwrap();
+	assert!(!tx.group_exists("Group").unwrap());
+	assert_eq!(tx.resolve_group_alias("Group").unwrap().as_deref(), Some("Again"));
+	assert_eq!(tx.resolve_group_alias("Renamed").unwrap().as_deref(), Some("Again"));
+	assert!(tx.check_edit_token("Again", Some(&token)).is_ok());
+	assert_eq!(tx.get_posts_in_group("Again", StarOrder::Position).unwrap().len(), 1);
+	assert_eq!(tx.get_group_tags("Again").unwrap().len(), 1);
+	tx.delete_group("Again").unwrap();
+	assert!(tx.resolve_group_alias("Group").unwrap().is_none());
+
+	tx.create_group("Guessable", Visibility::Public).unwrap();
+	tx.rename_group("Guessable", "Petname").unwrap();
+	let name = tx.unlist_group("Petname").unwrap();
+	assert_eq!(tx.get_group(&name).unwrap().unwrap().visibility, Visibility::Unlisted);
+	assert!(!tx.group_exists("Petname").unwrap());
+	assert!(tx.resolve_group_alias("Petname").unwrap().is_none());
+	assert!(tx.resolve_group_alias("Guessable").unwrap().is_none());
+}
+
+#[test]
+fn migrate_legacy_database_test() {
+	// schema as created before migrations were introduced
+	let mut db = Connection::open_in_memory().unwrap();
+	db.execute(
+		"CREATE TABLE stars(
+			group_name TEXT NOT NULL,
+			reddit_id TEXT NOT NULL,
+			reddit_data TEXT NOT NULL
+		) STRICT",
+		[],
+	)
+	.unwrap();
+	for id in ["a", "b", "a", "c"] {
+		db.execute(
+			"INSERT INTO stars (group_name, reddit_id, reddit_data) VALUES (?, ?, ?)",
+			params!["Group", id, serde_json::to_string(&test_post(id)).unwrap()],
+		)
+		.unwrap();
+	}
+	assert_eq!(schema_version(&db), 0);
+
+	migrate(&mut db).unwrap();
+	assert_eq!(schema_version(&db), MIGRATIONS.len());
+
+	let mut db = DB { db };
+	let tx = db.transaction().unwrap();
+	let posts = tx.get_posts_in_group("Group", StarOrder::Position).unwrap();
+	assert_eq!(posts.iter().map(|x| &*x.post.id).collect::<Vec<_>>(), ["a", "b", "c"]);
+	assert!(posts[0].info.starred_at.is_none());
+	assert!(tx.group_exists("Group").unwrap());
+	let group = tx.get_group("Group").unwrap().unwrap();
+	assert_eq!(group.visibility, Visibility::Public);
+	assert!(group.created_at.is_none());
+	assert!(tx.set_post_position("Group", "c", 0).unwrap());
+	let posts = tx.get_posts_in_group("Group", StarOrder::Position).unwrap();
+	assert_eq!(posts.iter().map(|x| &*x.post.id).collect::<Vec<_>>(), ["c", "a", "b"]);
+}
+
+#[tokio::test]
+async fn pool_test() {
+	let path = std::env::temp_dir().join(format!("reddit-image-grid-pool-test-{}.db", std::process::id()));
+	let pool = DbPool::open(path.to_str().unwrap()).unwrap();
+	let db = pool.get().unwrap();
+	let mode: String = (*db).db.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+	drop(db);
+	assert_eq!(mode, "wal");
+	let token = pool
+		.run(|db| {
+			let tx = db.transaction()?;
+			let token = tx.create_group("Group", Visibility::Public)?;
+			tx.commit()?;
+			Ok(token)
+		})
+		.await
+		.unwrap();
+	// a second connection sees the committed data
+	let first = pool.get().unwrap();
+	let mut second = pool.get().unwrap();
+	assert!(
+		second
+			.transaction()
+			.unwrap()
+			.check_edit_token("Group", Some(&token))
+			.is_ok()
+	);
+	drop((first, second));
+	assert_eq!(pool.inner.as_ref().unwrap().idle.lock().unwrap().len(), 2);
+
+	assert!(matches!(DbPool::disabled().get(), Err(DbError::NotConfigured)));
+	for suffix in ["", "-wal", "-shm"] {
+		let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+	}
 }
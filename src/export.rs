@@ -0,0 +1,242 @@
+use std::error::Error;
+use std::fs;
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use serde::Serialize;
+use tinytemplate::TinyTemplate;
+
+use crate::archive;
+use crate::cache::LruCache;
+use crate::database::{Group, StarredPost};
+use crate::reddit::{CLIENT, RedditDataPostData};
+
+/// Maximum size of a thumbnail embedded in the HTML export.
+const MAX_THUMBNAIL_SIZE: u64 = 1 << 20;
+/// Downloaded thumbnails of the HTML export as `data:` URIs, by URL.
+static THUMBNAIL_CACHE: LazyLock<Mutex<LruCache<String, String>>> =
+	LazyLock::new(|| Mutex::new(LruCache::new(500, Duration::from_secs(24 * 60 * 60))));
+/// Held by the HTML export downloading thumbnails.
+static DOWNLOADING: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+	name: &'a str,
+	title: Option<&'a str>,
+	description: Option<&'a str>,
+	visibility: &'static str,
+	created_at: Option<i64>,
+	posts: Vec<JsonPost<'a>>,
+}
+
+/// Post in the same shape as in Reddit listings, with the star metadata next to it.
+#[derive(Serialize)]
+struct JsonPost<'a> {
+	kind: &'static str,
+	data: &'a RedditDataPostData,
+	starred_at: Option<i64>,
+	source_sub: Option<&'a str>,
+	source_sort: Option<&'a str>,
+	source_time: Option<&'a str>,
+	note: Option<&'a str>,
+	tags: &'a [String],
+	starred_by: Option<&'a str>,
+}
+
+pub fn to_json(group: &Group, posts: &[StarredPost]) -> Result<String, Box<dyn Error>> {
+	let posts = posts
+		.iter()
+		.map(|x| JsonPost {
+			kind: "t3",
+			data: &x.post,
+			starred_at: x.info.starred_at,
+			source_sub: x.info.source_sub.as_deref(),
+			source_sort: x.info.source_sort.as_deref(),
+			source_time: x.info.source_time.as_deref(),
+			note: x.info.note.as_deref(),
+			tags: &x.info.tags,
+			starred_by: x.info.starred_by.as_deref(),
+		})
+		.collect();
+	Ok(serde_json::to_string_pretty(&JsonExport {
+		name: &group.name,
+		title: group.title.as_deref(),
+		description: group.description.as_deref(),
+		visibility: group.visibility.id(),
+		created_at: group.created_at,
+		posts,
+	})?)
+}
+
+pub fn to_csv(posts: &[StarredPost]) -> String {
+	let mut csv =
+		"id,title,subreddit,author,permalink,url,created_utc,starred_at,source_sub,source_sort,source_time,note,tags,starred_by\r\n"
+			.to_owned();
+	for x in posts {
+		let fields = [
+			x.post.id.clone(),
+			x.post.title.clone(),
+			x.post.subreddit.clone(),
+			x.post.author.clone(),
+			format!("https://www.reddit.com{}", x.post.permalink),
+			x.post.url.clone(),
+			x.post.created_utc.map(|x| (x as i64).to_string()).unwrap_or_default(),
+			x.info.starred_at.map(|x| x.to_string()).unwrap_or_default(),
+			x.info.source_sub.clone().unwrap_or_default(),
+			x.info.source_sort.clone().unwrap_or_default(),
+			x.info.source_time.clone().unwrap_or_default(),
+			x.info.note.clone().unwrap_or_default(),
+			x.info.tags.join(" "),
+			x.info.starred_by.clone().unwrap_or_default(),
+		];
+		csv += &fields.iter().map(|x| csv_field(x)).collect::<Vec<_>>().join(",");
+		csv += "\r\n";
+	}
+	csv
+}
+
+/// Quote the field if necessary (RFC 4180).
+fn csv_field(field: &str) -> String {
+	if field.contains([',', '"', '\r', '\n']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_owned()
+	}
+}
+
+#[derive(Serialize)]
+struct HtmlExport<'a> {
+	title: &'a str,
+	description: &'a str,
+	posts: Vec<HtmlPost>,
+}
+
+#[derive(Serialize)]
+struct HtmlPost {
+	title: String,
+	subreddit: String,
+	author: String,
+	permalink: String,
+	url: String,
+	thumbnail: String,
+	note: String,
+	starred_at: String,
+}
+
+/// Static page listing the posts, without any scripts or references to this server.
+/// Blocks while the thumbnails are downloaded (see [`DownloadBudget`]).
+pub fn to_html(group: &Group, posts: &[StarredPost]) -> Result<String, Box<dyn Error + Send + Sync>> {
+	let mut tt = TinyTemplate::new();
+	let templ = include_str!("export.html").replace("{\n", "\\{\n");
+	tt.add_template("export", &templ)?;
+	let mut budget = DownloadBudget::new();
+	let posts = posts
+		.iter()
+		.map(|x| HtmlPost {
+			title: x.post.title.clone(),
+			subreddit: x.post.subreddit.clone(),
+			author: x.post.author.clone(),
+			permalink: format!("https://www.reddit.com{}", x.post.permalink),
+			url: x.post.url.clone(),
+			thumbnail: thumbnail(&x.post, &mut budget).unwrap_or_default(),
+			note: x.info.note.clone().unwrap_or_default(),
+			starred_at: x.info.starred_at.map(format_date).unwrap_or_default(),
+		})
+		.collect();
+	Ok(tt.render("export", &HtmlExport {
+		title: group.title.as_deref().unwrap_or(&group.name),
+		description: group.description.as_deref().unwrap_or_default(),
+		posts,
+	})?)
+}
+
+/// Thumbnail as a `data:` URI, as the preview URLs expire. Prefers the archived image,
+/// then a cached or (within the budget) downloaded thumbnail.
+fn thumbnail(post: &RedditDataPostData, budget: &mut DownloadBudget) -> Option<String> {
+	if let Some(path) = archive::archived_image(&post.id)
+		&& let Ok(data) = fs::read(&path)
+	{
+		return Some(data_uri(archive::content_type(&path.to_string_lossy()), &data));
+	}
+	let url = post.thumbnail_url()?;
+	if let Some(uri) = THUMBNAIL_CACHE.lock().unwrap().get(&url) {
+		return Some(uri);
+	}
+	let timeout = budget.remaining()?;
+	let mut res = CLIENT
+		.get(&url)
+		.config()
+		.timeout_global(Some(timeout))
+		.build()
+		.call()
+		.ok()?;
+	let content_type = res.headers().get("content-type")?.to_str().ok()?.to_owned();
+	let data = res
+		.body_mut()
+		.with_config()
+		.limit(MAX_THUMBNAIL_SIZE)
+		.read_to_vec()
+		.ok()?;
+	budget.bytes_left = budget.bytes_left.saturating_sub(data.len());
+	if !content_type.starts_with("image/") {
+		return None;
+	}
+	let uri = data_uri(&content_type, &data);
+	THUMBNAIL_CACHE.lock().unwrap().insert(url, uri.clone());
+	Some(uri)
+}
+
+fn data_uri(content_type: &str, data: &[u8]) -> String {
+	format!("data:{content_type};base64,{}", BASE64_STANDARD.encode(data))
+}
+
+/// Limits the thumbnail downloads of one export, as anyone can export a public star list.
+/// Only one export downloads at a time, others only use archived and cached thumbnails.
+struct DownloadBudget {
+	lock: Option<MutexGuard<'static, ()>>,
+	deadline: Instant,
+	bytes_left: usize,
+}
+
+impl DownloadBudget {
+	fn new() -> Self {
+		DownloadBudget {
+			lock: DOWNLOADING.try_lock().ok(),
+			deadline: Instant::now() + Duration::from_secs(20),
+			bytes_left: 20 << 20,
+		}
+	}
+
+	/// Time left for downloads, `None` if the budget is used up.
+	fn remaining(&self) -> Option<Duration> {
+		let remaining = self.deadline.checked_duration_since(Instant::now())?;
+		(self.lock.is_some() && self.bytes_left > 0 && !remaining.is_zero()).then_some(remaining)
+	}
+}
+
+/// Format a Unix timestamp as a date (UTC), e.g. "2025-04-01".
+fn format_date(timestamp: i64) -> String {
+	// see https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+	let z = timestamp.div_euclid(86400) + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[test]
+fn export_format_test() {
+	assert_eq!(csv_field("plain"), "plain");
+	assert_eq!(csv_field("a, \"b\""), "\"a, \"\"b\"\"\"");
+	assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+	assert_eq!(format_date(0), "1970-01-01");
+	assert_eq!(format_date(951782400), "2000-02-29");
+	assert_eq!(format_date(1743465600), "2025-04-01");
+}
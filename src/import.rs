@@ -0,0 +1,172 @@
+use std::collections::HashSet;
+
+use rusqlite::Transaction;
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+use crate::database::{CommonQueries, DbError, StarInfo};
+use crate::normalize_tag;
+use crate::reddit::{self, BY_ID_LIMIT, RedditDataPostData};
+
+/// Post to import, possibly with its data already available.
+#[derive(Debug)]
+pub struct ImportItem {
+	pub id: String,
+	pub post: Option<RedditDataPostData>,
+	pub note: Option<String>,
+	pub tags: Vec<String>,
+}
+
+/// Result of [`parse`]: the posts found, and the parts of the input that were not understood.
+#[derive(Debug, Default)]
+pub struct ImportList {
+	pub items: Vec<ImportItem>,
+	pub unrecognized: Vec<String>,
+}
+
+/// Parse a list of post IDs or Reddit URLs (separated by whitespace or commas),
+/// or a Reddit JSON listing (e.g. saved posts or an export of a star list).
+pub fn parse(input: &str) -> ImportList {
+	let mut list = ImportList::default();
+	let json = input
+		.trim_start()
+		.starts_with(['{', '['])
+		.then(|| serde_json::from_str::<Value>(input).ok())
+		.flatten();
+	if let Some(json) = json {
+		collect_json_posts(&json, &mut list.items);
+	} else {
+		for x in input
+			.split(|c: char| c.is_whitespace() || c == ',')
+			.filter(|x| !x.is_empty())
+		{
+			match parse_post_id(x) {
+				Some(id) => list.items.push(ImportItem {
+					id,
+					post: None,
+					note: None,
+					tags: vec![],
+				}),
+				None => list.unrecognized.push(x.to_owned()),
+			}
+		}
+	}
+	let mut seen = HashSet::new();
+	list.items.retain(|x| seen.insert(x.id.clone()));
+	list
+}
+
+/// Find all posts (things of kind t3) in the JSON value.
+fn collect_json_posts(json: &Value, items: &mut Vec<ImportItem>) {
+	match json {
+		Value::Object(map) => {
+			if map.get("kind").and_then(Value::as_str) == Some("t3") {
+				if let Some(id) = map.get("data").and_then(|x| x.get("id")).and_then(Value::as_str) {
+					items.push(ImportItem {
+						id: id.to_owned(),
+						post: serde_json::from_value(map["data"].clone()).ok(),
+						note: map.get("note").and_then(Value::as_str).map(str::to_owned),
+						tags: map
+							.get("tags")
+							.and_then(Value::as_array)
+							.into_iter()
+							.flatten()
+							.filter_map(|x| normalize_tag(x.as_str()?).ok())
+							.collect(),
+					});
+				}
+				return;
+			}
+			for x in map.values() {
+				collect_json_posts(x, items);
+			}
+		},
+		Value::Array(values) => {
+			for x in values {
+				collect_json_posts(x, items);
+			}
+		},
+		_ => {},
+	}
+}
+
+/// Extract the post ID from a permalink, short link, fullname (t3_...) or plain ID.
+fn parse_post_id(input: &str) -> Option<String> {
+	let id = if let Some((_, rest)) = input.split_once("/comments/") {
+		rest.split('/').next()?
+	} else if let Some((_, rest)) = input.split_once("redd.it/") {
+		rest.split(['/', '?']).next()?
+	} else {
+		input.strip_prefix("t3_").unwrap_or(input)
+	};
+	let valid = !id.is_empty() && id.len() <= 12 && id.chars().all(|x| x.is_ascii_alphanumeric());
+	valid.then(|| id.to_ascii_lowercase())
+}
+
+/// Fetch the data of all posts that do not have it yet, in batches.
+/// Returns the IDs of posts that could not be found.
+pub async fn resolve(items: &mut Vec<ImportItem>) -> Result<Vec<String>, anyhow::Error> {
+	let missing: Vec<String> = items
+		.iter()
+		.filter(|x| x.post.is_none())
+		.map(|x| x.id.clone())
+		.collect();
+	for ids in missing.chunks(BY_ID_LIMIT) {
+		let (tx, rx) = oneshot::channel();
+		reddit::get_posts_by_id(ids.to_vec(), tx);
+		let json = rx.await??;
+		reddit::cache_posts(json.posts()).await;
+		for post in json.posts() {
+			if let Some(item) = items.iter_mut().find(|x| x.id == post.id) {
+				item.post = Some(post.clone());
+			}
+		}
+	}
+	let not_found = items
+		.iter()
+		.filter(|x| x.post.is_none())
+		.map(|x| x.id.clone())
+		.collect();
+	items.retain(|x| x.post.is_some());
+	Ok(not_found)
+}
+
+/// Add the resolved posts to the group, `member` being who imported them (not set for the owner).
+/// Returns the number of posts added.
+pub fn insert(tx: &Transaction, group: &str, items: Vec<ImportItem>, member: Option<String>) -> Result<usize, DbError> {
+	let mut count = 0;
+	for item in items {
+		let Some(post) = item.post else {
+			continue;
+		};
+		let info = StarInfo {
+			note: item.note,
+			tags: item.tags,
+			starred_by: member.clone(),
+			..Default::default()
+		};
+		tx.put_post_in_group(group, post, &info)?;
+		count += 1;
+	}
+	Ok(count)
+}
+
+#[test]
+fn import_parse_test() {
+	let list = parse(
+		"https://www.reddit.com/r/pics/comments/abc123/some_title/\n\
+		https://redd.it/DEF456, t3_ghi789 jkl012 abc123 not-a-post 12345",
+	);
+	let ids: Vec<_> = list.items.iter().map(|x| &*x.id).collect();
+	assert_eq!(ids, ["abc123", "def456", "ghi789", "jkl012", "12345"]);
+	assert_eq!(list.unrecognized, ["not-a-post"]);
+
+	let list = parse(
+		r#"{"kind": "Listing", "data": {"children": [{"kind": "t1", "data": {"id": "comment"}}, {"kind": "t3", "data": {"id": "abc"}, "note": "nice", "tags": ["Red", "not a tag"]}]}}"#,
+	);
+	assert_eq!(list.items.len(), 1);
+	assert_eq!(list.items[0].id, "abc");
+	assert_eq!(list.items[0].note.as_deref(), Some("nice"));
+	assert_eq!(list.items[0].tags, ["red"]);
+	assert!(list.items[0].post.is_none());
+}
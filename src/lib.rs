@@ -1,8 +1,10 @@
-use std::{env, error::Error, fmt::Display, sync::LazyLock};
+use std::{env, error::Error, fmt::Display, path::PathBuf, sync::LazyLock};
+
+use rand::{Rng, distributions::Alphanumeric};
 
 pub static USE_SERVER_FETCH: LazyLock<bool> = LazyLock::new(|| {
 	env::var("REDDIT_IMAGE_GRID_USE_SERVER_FETCH")
-		.map(|x| x != "" && x != "0")
+		.map(|x| !x.is_empty() && x != "0")
 		.unwrap_or(false)
 });
 pub static BASE_URL: LazyLock<String> = LazyLock::new(|| {
@@ -27,16 +29,56 @@ pub static PORT: LazyLock<u16> = LazyLock::new(|| {
 	val.unwrap()
 });
 pub static DATABASE_PATH: LazyLock<Option<String>> = LazyLock::new(|| env::var("REDDIT_IMAGE_GRID_DATABASE").ok());
+/// Directory to archive the media of starred posts in. Archiving is disabled if not set or empty.
+pub static ARCHIVE_DIR: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
+	env::var_os("REDDIT_IMAGE_GRID_ARCHIVE")
+		.filter(|x| !x.is_empty())
+		.map(PathBuf::from)
+});
+/// Starred posts are fetched again after this many seconds (0 disables this).
+pub static REFRESH_INTERVAL: LazyLock<u64> =
+	LazyLock::new(|| parse_env_var("REDDIT_IMAGE_GRID_REFRESH_INTERVAL", 24 * 60 * 60));
+/// Maximum number of posts kept in the post cache.
+pub static POST_CACHE_SIZE: LazyLock<usize> =
+	LazyLock::new(|| parse_env_var("REDDIT_IMAGE_GRID_POST_CACHE_SIZE", 10_000));
+/// Maximum age of posts in the post cache, in seconds.
+pub static POST_CACHE_MAX_AGE: LazyLock<u64> =
+	LazyLock::new(|| parse_env_var("REDDIT_IMAGE_GRID_POST_CACHE_MAX_AGE", 24 * 60 * 60));
+
+fn parse_env_var<T: std::str::FromStr>(name: &str, default: T) -> T {
+	let Ok(val) = env::var(name) else {
+		return default;
+	};
+	match val.parse() {
+		Ok(val) => val,
+		Err(_) => {
+			tracing::error!("{name} not valid");
+			std::process::exit(1);
+		},
+	}
+}
 
 pub fn force_lazy_vars() {
 	tracing::info!("base URL: {}", LazyLock::force(&BASE_URL));
 	tracing::info!("port: {}", LazyLock::force(&PORT));
 	tracing::info!("server-side JSON fetch: {:?}", LazyLock::force(&USE_SERVER_FETCH));
 	tracing::info!("database: {:?}", LazyLock::force(&DATABASE_PATH));
+	tracing::info!("media archive: {:?}", LazyLock::force(&ARCHIVE_DIR));
+	tracing::info!("refresh interval: {} seconds", LazyLock::force(&REFRESH_INTERVAL));
+	tracing::info!(
+		"post cache: {} posts, {} seconds",
+		LazyLock::force(&POST_CACHE_SIZE),
+		LazyLock::force(&POST_CACHE_MAX_AGE)
+	);
 }
 
+pub mod archive;
+pub mod cache;
 pub mod database;
+pub mod export;
+pub mod import;
 pub mod reddit;
+pub mod refresh;
 pub mod template;
 
 #[derive(Debug)]
@@ -62,6 +104,27 @@ impl Display for StringError {
 	}
 }
 
+/// Generate a random, unguessable token (e.g. to authorize edits).
+pub fn gen_token() -> String {
+	rand::thread_rng()
+		.sample_iter(&Alphanumeric)
+		.take(24)
+		.map(char::from)
+		.collect()
+}
+
+/// Tags of starred posts are stored in lower case.
+pub fn normalize_tag(tag: &str) -> Result<String, StringError> {
+	let tag = tag.trim().to_lowercase();
+	if tag.is_empty() || tag.chars().count() > 32 {
+		return Err(StringError("tag must have 1 to 32 characters"));
+	}
+	if !tag.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+		return Err(StringError("tag may only contain letters, digits, - and _"));
+	}
+	Ok(tag)
+}
+
 pub trait UppercaseFirst {
 	fn uppercase_first(&self) -> String;
 }
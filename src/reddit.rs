@@ -1,18 +1,21 @@
 use std::{
-	collections::HashMap,
+	collections::{BTreeMap, HashMap},
+	error::Error,
 	fmt,
 	str::FromStr,
 	sync::{LazyLock, RwLock},
+	time::Duration,
 };
 
 use serde::{Deserialize, Serialize};
 use tokio::sync::{
 	mpsc::{UnboundedSender, unbounded_channel},
-	oneshot::Sender,
+	oneshot::{self, Sender},
 };
 use ureq::Agent;
 
-use crate::StringError;
+use crate::cache::LruCache;
+use crate::{POST_CACHE_MAX_AGE, POST_CACHE_SIZE, StringError};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Sort {
@@ -117,9 +120,9 @@ pub struct SizedImage {
 	pub src_url: String,
 }
 
-static CLIENT: LazyLock<Agent> = LazyLock::new(|| {
+pub(crate) static CLIENT: LazyLock<Agent> = LazyLock::new(|| {
 	Agent::config_builder()
-		.user_agent(&format!(
+		.user_agent(format!(
 			"linux:reddit-image-grid:{} (by /u/username)",
 			env!("CARGO_PKG_VERSION")
 		))
@@ -127,47 +130,144 @@ static CLIENT: LazyLock<Agent> = LazyLock::new(|| {
 		.into()
 });
 
-static WORK_QUEUE: RwLock<
-	Option<UnboundedSender<(String, Sort, Time, u64, Sender<Result<Vec<Post>, anyhow::Error>>)>>,
-> = RwLock::new(None);
+/// Raw data of recently seen posts, by ID. Used to star posts.
+pub static POST_CACHE: LazyLock<tokio::sync::Mutex<LruCache<String, RedditDataPostData>>> = LazyLock::new(|| {
+	tokio::sync::Mutex::new(LruCache::new(
+		*POST_CACHE_SIZE,
+		Duration::from_secs(*POST_CACHE_MAX_AGE),
+	))
+});
+
+pub async fn cache_posts<'a>(posts: impl IntoIterator<Item = &'a RedditDataPostData>) {
+	let mut cache = POST_CACHE.lock().await;
+	for x in posts {
+		cache.insert(x.id.clone(), x.clone());
+	}
+	tracing::debug!("post cache: {} posts, {}", cache.len(), cache.stats());
+}
+
+type WorkResult = Sender<Result<RedditData, RedditError>>;
+
+enum WorkItem {
+	Listing {
+		sub: String,
+		sort: Sort,
+		time: Time,
+		limit: u64,
+		tx: WorkResult,
+	},
+	ById {
+		ids: Vec<String>,
+		tx: WorkResult,
+	},
+}
+
+static WORK_QUEUE: RwLock<Option<UnboundedSender<WorkItem>>> = RwLock::new(None);
 
 pub fn work() {
 	let (tx, mut rx) = unbounded_channel();
 	WORK_QUEUE.write().unwrap().replace(tx);
 	while let Some(work) = rx.blocking_recv() {
-		let res = get_posts_internal(&*CLIENT, &work.0, work.1, work.2, work.3);
-		let _ = work.4.send(res);
+		match work {
+			WorkItem::Listing {
+				sub,
+				sort,
+				time,
+				limit,
+				tx,
+			} => {
+				let _ = tx.send(fetch_json(&CLIENT, &make_request_url(&sub, sort, time, limit)));
+			},
+			WorkItem::ById { ids, tx } => {
+				let _ = tx.send(fetch_json(&CLIENT, &make_by_id_url(&ids)));
+			},
+		}
 	}
 }
 
-pub fn get_posts(
-	sub: String,
-	sort: Sort,
-	time: Time,
-	limit: u64,
-	tx_instance: Sender<Result<Vec<Post>, anyhow::Error>>,
-) {
+fn queue_work(item: WorkItem) {
 	if let Some(tx) = WORK_QUEUE.read().unwrap().as_ref() {
-		tx.send((sub, sort, time, limit, tx_instance)).unwrap();
+		tx.send(item).unwrap();
+	}
+}
+
+pub fn get_posts(sub: String, sort: Sort, time: Time, limit: u64, tx: WorkResult) {
+	queue_work(WorkItem::Listing {
+		sub,
+		sort,
+		time,
+		limit,
+		tx,
+	});
+}
+
+/// Fetch the posts with the given IDs (at most [`BY_ID_LIMIT`]).
+pub fn get_posts_by_id(ids: Vec<String>, tx: WorkResult) {
+	queue_work(WorkItem::ById { ids, tx });
+}
+
+/// Get a single post, from the cache or from Reddit.
+pub async fn get_post(id: &str) -> Result<RedditDataPostData, anyhow::Error> {
+	if let Some(post) = POST_CACHE.lock().await.get(id) {
+		return Ok(post);
+	}
+	if id.is_empty() || !id.chars().all(|x| x.is_ascii_alphanumeric()) {
+		return Err(StringError("invalid post ID").into());
 	}
+	let (tx, rx) = oneshot::channel();
+	get_posts_by_id(vec![id.to_owned()], tx);
+	let json = rx.await??;
+	cache_posts(json.posts()).await;
+	json.data
+		.children
+		.into_iter()
+		.map(|x| x.data)
+		.find(|x| x.id == id)
+		.ok_or_else(|| PostNotFound.into())
 }
 
+/// Maximum number of posts that can be fetched by ID in one request.
+pub const BY_ID_LIMIT: usize = 100;
+
 pub fn make_request_url(sub: &str, sort: Sort, time: Time, limit: u64) -> String {
 	format!("https://www.reddit.com/r/{sub}/{sort}.json?limit={limit}&t={time}&show=all")
 }
 
-fn get_posts_internal(
-	client: &Agent,
-	sub: &str,
-	sort: Sort,
-	time: Time,
-	limit: u64,
-) -> Result<Vec<Post>, anyhow::Error> {
-	let req = client.get(make_request_url(sub, sort, time, limit));
-	let mut res = req.call()?;
-	let body = res.body_mut();
-	let json: RedditData = body.read_json()?;
-	parse_json(json, sub, sort, time)
+pub fn make_by_id_url(ids: &[String]) -> String {
+	format!("https://www.reddit.com/by_id/t3_{}.json", ids.join(",t3_"))
+}
+
+/// Reddit could not be reached or sent an unexpected response.
+#[derive(Debug)]
+pub struct RedditError(ureq::Error);
+
+impl fmt::Display for RedditError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Reddit request failed: {}", self.0)
+	}
+}
+
+impl Error for RedditError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		Some(&self.0)
+	}
+}
+
+/// Reddit does not know the post (or does not list it anymore).
+#[derive(Debug)]
+pub struct PostNotFound;
+
+impl fmt::Display for PostNotFound {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "post not found")
+	}
+}
+
+impl Error for PostNotFound {}
+
+fn fetch_json(client: &Agent, url: &str) -> Result<RedditData, RedditError> {
+	let mut res = client.get(url).call().map_err(RedditError)?;
+	res.body_mut().read_json().map_err(RedditError)
 }
 
 pub fn parse_json(json: RedditData, sub: &str, sort: Sort, time: Time) -> Result<Vec<Post>, anyhow::Error> {
@@ -252,7 +352,7 @@ pub fn parse_json(json: RedditData, sub: &str, sort: Sort, time: Time) -> Result
 			}
 		} else if let Some(mm) = x.media_metadata {
 			count_mm += 1;
-			for x in mm.values() {
+			for x in gallery_items(&mm, x.gallery_data.as_ref()) {
 				if let Some(url) = x.s.mp4.as_ref() {
 					posts.push(Post {
 						width: x.s.x,
@@ -303,12 +403,7 @@ pub fn parse_json(json: RedditData, sub: &str, sort: Sort, time: Time) -> Result
 			count_preview += 1;
 			for img in p.images {
 				let img_box = Box::new(img);
-				let img = img_box
-					.variants
-					.as_ref()
-					.map(|x| x.get("mp4"))
-					.flatten()
-					.unwrap_or(&img_box);
+				let img = img_box.variants.as_ref().and_then(|x| x.get("mp4")).unwrap_or(&img_box);
 				let mut sizes: Vec<_> = img
 					.resolutions
 					.iter()
@@ -395,21 +490,55 @@ struct RedditDataPost {
 
 #[derive(Deserialize, Debug, Serialize, Clone)]
 pub struct RedditDataPostData {
-	title: String,
-	url: String,
-	subreddit: String,
-	author: String,
-	permalink: String,
+	pub title: String,
+	pub url: String,
+	pub subreddit: String,
+	pub author: String,
+	pub permalink: String,
 	pub id: String,
+	/// Unix timestamp
+	pub created_utc: Option<f64>,
 	// Do not use thumbnail_height/thumbnail_width, they are highly unreliable.
 	/// Available for videos
 	secure_media: Option<RedditDataSecureMedia>,
 	/// Available for videos / non-reddit-hosted images
 	preview: Option<RedditDataPreview>,
-	/// Available for images / galleries
-	media_metadata: Option<HashMap<String, RedditDataMediaImage>>,
+	/// Available for images / galleries. Ordered by `gallery_data`, see [`gallery_items`].
+	media_metadata: Option<BTreeMap<String, RedditDataMediaImage>>,
+	/// Available for galleries: the order of the items
+	gallery_data: Option<RedditDataGalleryData>,
 	/// If not null: removed for that reason (e.g. copyright)
-	removed_by_category: Option<String>,
+	pub removed_by_category: Option<String>,
+}
+
+impl RedditDataPostData {
+	/// URL of a small preview image, if any.
+	pub fn thumbnail_url(&self) -> Option<String> {
+		const MIN_WIDTH: usize = 320;
+		let url = if let Some(image) = self.preview.as_ref().and_then(|x| x.images.first()) {
+			image
+				.resolutions
+				.iter()
+				.find(|x| x.width >= MIN_WIDTH)
+				.unwrap_or(&image.source)
+				.url
+				.clone()
+		} else if let Some(image) = self
+			.media_metadata
+			.as_ref()
+			.and_then(|x| gallery_items(x, self.gallery_data.as_ref()).first().copied())
+		{
+			image
+				.p
+				.iter()
+				.find(|x| x.x >= MIN_WIDTH)
+				.and_then(|x| x.u.clone())
+				.or_else(|| image.s.u.clone())?
+		} else {
+			self.secure_media.as_ref()?.oembed.as_ref()?.thumbnail_url.clone()
+		};
+		Some(url.replace("&amp;", "&"))
+	}
 }
 
 #[derive(Deserialize, Debug, Serialize, Clone)]
@@ -456,6 +585,32 @@ struct RedditDataImage1 {
 	height: usize,
 }
 
+#[derive(Deserialize, Debug, Serialize, Clone)]
+struct RedditDataGalleryData {
+	items: Vec<RedditDataGalleryItem>,
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone)]
+struct RedditDataGalleryItem {
+	media_id: String,
+}
+
+/// Media items in the order of the gallery. Posts stored without `gallery_data`
+/// fall back to the order of the media IDs.
+fn gallery_items<'a>(
+	media_metadata: &'a BTreeMap<String, RedditDataMediaImage>,
+	gallery_data: Option<&RedditDataGalleryData>,
+) -> Vec<&'a RedditDataMediaImage> {
+	match gallery_data {
+		Some(gallery) => gallery
+			.items
+			.iter()
+			.filter_map(|x| media_metadata.get(&x.media_id))
+			.collect(),
+		None => media_metadata.values().collect(),
+	}
+}
+
 #[derive(Deserialize, Debug, Serialize, Clone)]
 struct RedditDataMediaImage {
 	p: Vec<RedditDataMediaImage1>,
@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+use rusqlite::Transaction;
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::REFRESH_INTERVAL;
+use crate::database::{CommonQueries, DbError, DbPool};
+use crate::reddit::{self, BY_ID_LIMIT, RedditData};
+
+/// Maximum number of posts refreshed in one pass of the background job.
+const MAX_POSTS_PER_PASS: usize = 10_000;
+
+#[derive(Debug, Default, Serialize)]
+pub struct RefreshResult {
+	pub updated: usize,
+	pub removed: usize,
+}
+
+/// Periodically fetch the data of starred posts again, so that preview URLs do not expire.
+/// Does nothing if the database or the refresh interval is not configured.
+pub fn work(db: DbPool) {
+	let interval = *REFRESH_INTERVAL;
+	if interval == 0 || !db.is_enabled() {
+		return;
+	}
+	loop {
+		match refresh_stale(&db, interval) {
+			Ok(res) if res.updated > 0 => {
+				tracing::info!("refreshed {} starred posts, {} removed", res.updated, res.removed)
+			},
+			Ok(_) => {},
+			Err(e) => tracing::warn!("failed to refresh starred posts: {e:?}"),
+		}
+		thread::sleep(Duration::from_secs(interval.min(3600)));
+	}
+}
+
+fn refresh_stale(db: &DbPool, max_age: u64) -> Result<RefreshResult, Box<dyn Error>> {
+	let mut db = db.get()?;
+	let ids = db.transaction()?.get_stale_posts(max_age, MAX_POSTS_PER_PASS)?;
+	let mut res = RefreshResult::default();
+	for ids in ids.chunks(BY_ID_LIMIT) {
+		let (tx, rx) = oneshot::channel();
+		reddit::get_posts_by_id(ids.to_vec(), tx);
+		let json = rx.blocking_recv()??;
+		let tx = db.transaction()?;
+		apply(&tx, ids, &json, &mut res)?;
+		tx.commit()?;
+		// do not flood Reddit with requests
+		thread::sleep(Duration::from_secs(2));
+	}
+	Ok(res)
+}
+
+/// Store the fetched post data of the requested posts.
+/// Posts Reddit did not return are marked as removed, so they are not requested again.
+pub fn apply(tx: &Transaction, ids: &[String], json: &RedditData, res: &mut RefreshResult) -> Result<(), DbError> {
+	let posts = json.posts();
+	for post in &posts {
+		tx.update_post_data(post)?;
+		res.updated += 1;
+		if post.removed_by_category.is_some() {
+			res.removed += 1;
+		}
+	}
+	for id in ids.iter().filter(|id| !posts.iter().any(|x| &x.id == *id)) {
+		tx.mark_post_removed(id)?;
+		res.updated += 1;
+		res.removed += 1;
+	}
+	Ok(())
+}
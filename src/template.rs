@@ -1,48 +1,165 @@
-use std::error::Error;
+use std::{
+	collections::HashMap,
+	error::Error,
+	str::FromStr,
+	time::{SystemTime, UNIX_EPOCH},
+};
 
 use serde::Serialize;
 use tinytemplate::TinyTemplate;
 use tokio::sync::oneshot;
 
 use crate::{
-	BASE_URL, USE_SERVER_FETCH,
+	BASE_URL, USE_SERVER_FETCH, archive,
+	database::{Group, GroupSummary, StarInfo, StarOrder, StarredPost, Visibility},
 	reddit::{self, RedditData, Sort, Time, make_request_url},
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+	/// CSS grid, each card spans a number of grid cells.
+	Grid,
+	/// Justified rows of equal height (Flickr style).
+	Rows,
+}
+
+impl Layout {
+	pub fn id(&self) -> &'static str {
+		match self {
+			Layout::Grid => "grid",
+			Layout::Rows => "rows",
+		}
+	}
+}
+
+impl FromStr for Layout {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		Ok(match s {
+			"grid" => Layout::Grid,
+			"rows" => Layout::Rows,
+			_ => return Err("invalid layout parameter"),
+		})
+	}
+}
+
+/// Default number of columns (grid) or images per row (rows).
+pub const DEFAULT_COLUMNS: usize = 4;
+/// Gap between cards, in pixels. Must match the CSS.
+const GAP: usize = 5;
+
 pub struct TemplateParameters {
 	sub: Option<String>,
 	sort: Option<Sort>,
 	time: Option<Time>,
 	autoplay: bool,
+	layout: Layout,
+	columns: usize,
 	data: Option<RedditData>,
 	star_view: bool,
+	star_order: StarOrder,
+	/// Star list to display
+	group: Option<Group>,
+	/// Star metadata by post ID
+	star_info: HashMap<String, StarInfo>,
+	/// Tags of the star list with their number of posts
+	tags: Vec<(String, usize)>,
+	/// Only posts with this tag are shown
+	tag_filter: Option<String>,
+	/// Star lists the posts were collected from, with their number of posts
+	group_facets: Vec<(String, usize)>,
+	/// Only posts of this star list are shown
+	group_filter: Option<String>,
+	/// Posts collected from several star lists, which cannot be edited here
+	read_only: bool,
 }
 
 impl TemplateParameters {
 	/// Render full UI.
-	pub fn render_ui(sub: Option<String>, sort: Option<Sort>, time: Option<Time>, autoplay: bool) -> Self {
+	pub fn render_ui(
+		sub: Option<String>,
+		sort: Option<Sort>,
+		time: Option<Time>,
+		autoplay: bool,
+		layout: Layout,
+		columns: usize,
+	) -> Self {
 		TemplateParameters {
 			sub,
 			sort,
 			time,
 			autoplay,
+			layout,
+			columns,
 			data: None,
 			star_view: false,
+			star_order: StarOrder::Position,
+			group: None,
+			star_info: HashMap::new(),
+			tags: vec![],
+			tag_filter: None,
+			group_facets: vec![],
+			group_filter: None,
+			read_only: false,
 		}
 	}
 
 	/// Render full UI.
-	pub fn render_ui_stars(group: String, autoplay: bool, data: RedditData) -> Self {
+	pub fn render_ui_stars(
+		group: Group,
+		autoplay: bool,
+		layout: Layout,
+		columns: usize,
+		order: StarOrder,
+		stars: Vec<StarredPost>,
+	) -> Self {
+		let mut star_info = HashMap::new();
+		let mut posts = vec![];
+		for star in stars {
+			star_info.insert(star.post.id.clone(), star.info);
+			posts.push(star.post);
+		}
 		TemplateParameters {
-			sub: Some(group),
+			sub: Some(group.name.clone()),
 			sort: None,
 			time: None,
 			autoplay,
-			data: Some(data),
+			layout,
+			columns,
+			data: Some(RedditData::from_posts(posts)),
 			star_view: true,
+			star_order: order,
+			group: Some(group),
+			star_info,
+			tags: vec![],
+			tag_filter: None,
+			group_facets: vec![],
+			group_filter: None,
+			read_only: false,
 		}
 	}
 
+	/// Show the tags of the star list, `filter` being the tag the posts were filtered by.
+	pub fn with_tags(mut self, tags: Vec<(String, usize)>, filter: Option<String>) -> Self {
+		self.tags = tags;
+		self.tag_filter = filter;
+		self
+	}
+
+	/// Show the star lists the posts were collected from, `filter` being the star list the posts were filtered by.
+	pub fn with_group_facets(mut self, groups: Vec<(String, usize)>, filter: Option<String>) -> Self {
+		self.group_facets = groups;
+		self.group_filter = filter;
+		self
+	}
+
+	/// Hide all controls to edit the star list and its posts.
+	pub fn read_only(mut self) -> Self {
+		self.read_only = true;
+		self
+	}
+
 	/// Render only the provided grid items.
 	/// Used as AJAX response.
 	pub fn render_grid_items(
@@ -50,6 +167,8 @@ impl TemplateParameters {
 		sort: Option<Sort>,
 		time: Option<Time>,
 		autoplay: bool,
+		layout: Layout,
+		columns: usize,
 		data: Option<RedditData>,
 	) -> Self {
 		TemplateParameters {
@@ -57,8 +176,18 @@ impl TemplateParameters {
 			sort,
 			time,
 			autoplay,
+			layout,
+			columns,
 			data,
 			star_view: false,
+			star_order: StarOrder::Position,
+			group: None,
+			star_info: HashMap::new(),
+			tags: vec![],
+			tag_filter: None,
+			group_facets: vec![],
+			group_filter: None,
+			read_only: false,
 		}
 	}
 
@@ -69,8 +198,18 @@ impl TemplateParameters {
 			sort: None,
 			time: None,
 			autoplay: false,
+			layout: Layout::Grid,
+			columns: DEFAULT_COLUMNS,
 			data: None,
 			star_view: false,
+			star_order: StarOrder::Position,
+			group: None,
+			star_info: HashMap::new(),
+			tags: vec![],
+			tag_filter: None,
+			group_facets: vec![],
+			group_filter: None,
+			read_only: false,
 		}
 	}
 }
@@ -80,8 +219,19 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 	let sort = params.sort;
 	let time = params.time;
 	let autoplay = params.autoplay;
+	let layout = params.layout;
+	let columns = params.columns.clamp(1, 10);
 	let data = params.data;
 	let star_view = params.star_view;
+	let read_only = params.read_only;
+	let star_order = params.star_order;
+	let group = params.group;
+	let visibility = group.as_ref().map(|x| x.visibility).unwrap_or(Visibility::Public);
+	let star_info = params.star_info;
+	let tag_filter = params.tag_filter.unwrap_or_default();
+	let tags = facets(params.tags, &tag_filter);
+	let group_filter = params.group_filter.unwrap_or_default();
+	let group_facets = facets(params.group_facets, &group_filter);
 	let full_page = data.is_none() || star_view;
 	let limit = 25;
 	let time = time.unwrap_or(Time::Day);
@@ -95,16 +245,37 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 		} else if *USE_SERVER_FETCH {
 			let (tx, rx) = oneshot::channel();
 			reddit::get_posts(x.clone(), sort, time, limit, tx);
-			rx.await??
+			let json = rx.await??;
+			reddit::cache_posts(json.posts()).await;
+			reddit::parse_json(json, x, sort, time)?
 		} else if let Some(json) = data {
 			reddit::parse_json(json, x, sort, time)?
 		} else {
 			vec![]
 		};
+		// position of the card within its post (for galleries)
+		let mut media_index = HashMap::new();
 		for p in posts {
 			let reddit_id = p.id;
-			let width;
-			let height;
+			let media_index = media_index
+				.entry(reddit_id.clone())
+				.and_modify(|x| *x += 1)
+				.or_insert(0);
+			let archive = if star_view {
+				archive::archived_url(&reddit_id, *media_index).unwrap_or_default()
+			} else {
+				String::new()
+			};
+			let note = star_info
+				.get(&reddit_id)
+				.and_then(|x| x.note.clone())
+				.unwrap_or_default();
+			let removed = star_info.get(&reddit_id).is_some_and(|x| x.removed);
+			let tags = star_info.get(&reddit_id).map(|x| x.tags.clone()).unwrap_or_default();
+			let starred_by = star_info
+				.get(&reddit_id)
+				.and_then(|x| x.starred_by.clone())
+				.unwrap_or_default();
 			// let class;
 			let w = p.width as f32;
 			let h = p.height as f32;
@@ -140,10 +311,11 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 				}
 			}
 			*/
-			width = 20.0;
-			height = h / w * width;
+			let width = 20.0;
+			let height = h / w * width;
+			let aspect = w / h;
 			match p.details {
-				reddit::PostDetails::Image { src_url, sizes } => {
+				reddit::PostDetails::Image { src_url, sizes: _ } => {
 					//let sizes_val = sizes.iter().rev().map(|x| format!("(min-width: {}px) 100%", x.width)).join(", ");
 					//let srcset = sizes.into_iter().rev().map(|x| format!("{} {}w", x.src_url, x.width)).join(", ");
 					cards.push(Card {
@@ -156,6 +328,9 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 						//sizes: sizes_val,
 						width: width.round() as _,
 						height: height.ceil() as _,
+						index: 0,
+						aspect,
+						style: String::new(),
 						class: "fix-width",
 						sub: p.sub,
 						user: p.author,
@@ -163,6 +338,11 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 						permalink: p.permalink,
 						reddit_id,
 						url: p.url,
+						note: note.clone(),
+						archive: archive.clone(),
+						removed,
+						tags: tags.clone(),
+						starred_by: starred_by.clone(),
 					});
 				},
 				reddit::PostDetails::Video { hls_url } => {
@@ -175,6 +355,9 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 						is_embed: false,
 						width: width.round() as _,
 						height: height.ceil() as _,
+						index: 0,
+						aspect,
+						style: String::new(),
 						class: "fix-width",
 						sub: p.sub,
 						user: p.author,
@@ -182,17 +365,25 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 						permalink: p.permalink,
 						reddit_id,
 						url: p.url,
+						note: note.clone(),
+						archive: archive.clone(),
+						removed,
+						tags: tags.clone(),
+						starred_by: starred_by.clone(),
 					});
 				},
 				reddit::PostDetails::VideoMp4 { mp4_urls } => {
 					cards.push(Card {
 						src: String::new(),
-						mp4_urls: mp4_urls,
+						mp4_urls,
 						is_hls: false,
 						is_mp4: true,
 						is_embed: false,
 						width: width.round() as _,
 						height: height.ceil() as _,
+						index: 0,
+						aspect,
+						style: String::new(),
 						class: "fix-width",
 						sub: p.sub,
 						user: p.author,
@@ -200,6 +391,11 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 						permalink: p.permalink,
 						reddit_id,
 						url: p.url,
+						note: note.clone(),
+						archive: archive.clone(),
+						removed,
+						tags: tags.clone(),
+						starred_by: starred_by.clone(),
 					});
 				},
 				reddit::PostDetails::VideoEmbed { html } => {
@@ -211,6 +407,9 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 						is_embed: true,
 						width: width.round() as _,
 						height: height.ceil() as _,
+						index: 0,
+						aspect,
+						style: String::new(),
 						class: "fix-width",
 						sub: p.sub,
 						user: p.author,
@@ -218,19 +417,34 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 						permalink: p.permalink,
 						reddit_id,
 						url: p.url,
+						note: note.clone(),
+						archive: archive.clone(),
+						removed,
+						tags: tags.clone(),
+						starred_by: starred_by.clone(),
 					});
 				},
 			}
 		}
 	}
+	let have_data = !cards.is_empty();
+	for (index, card) in cards.iter_mut().enumerate() {
+		card.index = index;
+	}
+	let rows = match layout {
+		Layout::Grid => grid_rows(cards),
+		Layout::Rows => justified_rows(cards, columns),
+	};
 	let title;
-	if let Some(sub) = &sub {
+	if let Some(group_title) = group.as_ref().and_then(|x| x.title.clone()) {
+		title = group_title;
+	} else if let Some(sub) = &sub {
 		if sort != Sort::Hot && sort != Sort::New {
 			title = format!("{sub} · {} · {}", sort.id(), time.id())
 		} else if sort != Sort::Hot {
 			title = format!("{sub} · {}", sort.id());
 		} else {
-			title = format!("{sub}");
+			title = sub.to_string();
 		}
 	} else {
 		title = "Reddit Image Grid".to_owned();
@@ -244,7 +458,7 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 	tt.add_template("grid", &templ)?;
 	Ok(tt.render("grid", &Context {
 		full_page,
-		have_data: !cards.is_empty(),
+		have_data,
 		fetch_url: sub
 			.as_ref()
 			.map(|sub| make_request_url(sub, sort, time, limit))
@@ -265,7 +479,10 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 		sort_new: sort == Sort::New,
 		sort_top: sort == Sort::Top,
 		sort_hot: sort == Sort::Hot,
-		cards,
+		rows,
+		layout: layout.id(),
+		rows_layout: layout == Layout::Rows,
+		columns,
 		time_hour: time == Time::Hour,
 		time_day: time == Time::Day,
 		time_week: time == Time::Week,
@@ -274,16 +491,133 @@ pub async fn get(params: TemplateParameters) -> Result<String, Box<dyn Error>> {
 		time_all: time == Time::All,
 		any_hls,
 		star_view,
+		read_only,
+		order_position: star_order == StarOrder::Position,
+		order_starred: star_order == StarOrder::Starred,
+		order_posted: star_order == StarOrder::Posted,
+		visibility_public: visibility == Visibility::Public,
+		visibility_unlisted: visibility == Visibility::Unlisted,
+		visibility_private: visibility == Visibility::Private,
+		group_title: group.as_ref().and_then(|x| x.title.clone()).unwrap_or_default(),
+		group_description: group.and_then(|x| x.description).unwrap_or_default(),
+		tags,
+		tag_filter,
+		group_facets,
+		group_filter,
 	})?)
 }
 
+fn facets(counts: Vec<(String, usize)>, active: &str) -> Vec<FacetContext> {
+	counts
+		.into_iter()
+		.map(|(name, count)| FacetContext {
+			active: name == active,
+			name,
+			count,
+		})
+		.collect()
+}
+
+/// Render the list of star lists.
+pub fn get_group_index(groups: Vec<GroupSummary>) -> Result<String, Box<dyn Error>> {
+	let mut tt = TinyTemplate::new();
+	let templ = include_str!("groups.html").replace("{\n", "\\{\n");
+	tt.add_template("groups", &templ)?;
+	let groups = groups
+		.into_iter()
+		.map(|x| GroupContext {
+			cover: x.cover.and_then(|x| x.thumbnail_url()).unwrap_or_default(),
+			name: x.group.name,
+			title: x.group.title.unwrap_or_default(),
+			description: x.group.description.unwrap_or_default(),
+			post_count: match x.post_count {
+				1 => "1 post".to_owned(),
+				n => format!("{n} posts"),
+			},
+			last_updated: x.last_updated.map(format_age).unwrap_or_default(),
+		})
+		.collect();
+	Ok(tt.render("groups", &GroupIndexContext {
+		base_url: &BASE_URL,
+		groups,
+	})?)
+}
+
+/// Page shown to browsers when a request fails.
+pub fn get_error_page(status: u16, reason: &str, message: &str) -> Result<String, Box<dyn Error>> {
+	let mut tt = TinyTemplate::new();
+	let templ = include_str!("error.html").replace("{\n", "\\{\n");
+	tt.add_template("error", &templ)?;
+	Ok(tt.render("error", &ErrorContext {
+		base_url: &BASE_URL,
+		status,
+		reason,
+		message,
+		server_error: status >= 500,
+		retry: status == 502,
+	})?)
+}
+
+/// Format a Unix timestamp relative to now, e.g. "3 days ago".
+fn format_age(timestamp: i64) -> String {
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|x| x.as_secs() as i64)
+		.unwrap_or_default();
+	let age = (now - timestamp).max(0);
+	let (value, unit) = if age < 60 {
+		return "just now".to_owned();
+	} else if age < 3600 {
+		(age / 60, "minute")
+	} else if age < 86400 {
+		(age / 3600, "hour")
+	} else if age < 30 * 86400 {
+		(age / 86400, "day")
+	} else if age < 365 * 86400 {
+		(age / (30 * 86400), "month")
+	} else {
+		(age / (365 * 86400), "year")
+	};
+	format!("{value} {unit}{} ago", if value == 1 { "" } else { "s" })
+}
+
+#[derive(Serialize)]
+struct ErrorContext<'a> {
+	base_url: &'static str,
+	status: u16,
+	reason: &'a str,
+	message: &'a str,
+	server_error: bool,
+	/// The error is probably temporary, e.g. Reddit is down
+	retry: bool,
+}
+
+#[derive(Serialize)]
+struct GroupIndexContext {
+	base_url: &'static str,
+	groups: Vec<GroupContext>,
+}
+
+#[derive(Serialize)]
+struct GroupContext {
+	name: String,
+	title: String,
+	description: String,
+	post_count: String,
+	last_updated: String,
+	cover: String,
+}
+
 #[derive(Serialize)]
 struct Context {
 	full_page: bool,
 	have_data: bool,
 	fetch_url: String,
 	title: String,
-	cards: Vec<Card>,
+	rows: Vec<Row>,
+	layout: &'static str,
+	rows_layout: bool,
+	columns: usize,
 	sort_top: bool,
 	sort_new: bool,
 	sort_controversial: bool,
@@ -304,6 +638,85 @@ struct Context {
 	time_all: bool,
 	any_hls: bool,
 	star_view: bool,
+	read_only: bool,
+	order_position: bool,
+	order_starred: bool,
+	order_posted: bool,
+	visibility_public: bool,
+	visibility_unlisted: bool,
+	visibility_private: bool,
+	group_title: String,
+	group_description: String,
+	tags: Vec<FacetContext>,
+	tag_filter: String,
+	group_facets: Vec<FacetContext>,
+	group_filter: String,
+}
+
+/// Tag or star list the posts can be filtered by.
+#[derive(Serialize)]
+struct FacetContext {
+	name: String,
+	count: usize,
+	/// Posts are filtered by this
+	active: bool,
+}
+
+/// Place all cards in a single row, the CSS grid does the rest.
+fn grid_rows(mut cards: Vec<Card>) -> Vec<Row> {
+	for card in &mut cards {
+		card.style = format!(
+			"grid-column-end: span {}; grid-row-end: span {};",
+			card.width, card.height
+		);
+	}
+	vec![Row { cards }]
+}
+
+/// Split cards into rows of equal height that fill the viewport width.
+///
+/// A row is closed once the aspect ratios of its cards sum up to `columns`,
+/// i.e. a row holds about as much as `columns` square images.
+/// Card widths are then proportional to their aspect ratio.
+/// The last row is not stretched if it is incomplete.
+fn justified_rows(cards: Vec<Card>, columns: usize) -> Vec<Row> {
+	let target = columns as f32;
+	let mut rows = vec![];
+	let mut row: Vec<Card> = vec![];
+	let mut row_aspect = 0.0;
+	let close_row = |row: Vec<Card>, row_aspect: f32, rows: &mut Vec<Row>| {
+		let total = row_aspect.max(target);
+		let gaps = (row.len() - 1) * GAP;
+		let cards = row
+			.into_iter()
+			.map(|mut card| {
+				card.style = format!(
+					"flex: 0 0 calc((100% - {gaps}px) * {:.5}); aspect-ratio: {:.5};",
+					card.aspect / total,
+					card.aspect
+				);
+				card
+			})
+			.collect();
+		rows.push(Row { cards });
+	};
+	for card in cards {
+		row_aspect += card.aspect;
+		row.push(card);
+		if row_aspect >= target {
+			close_row(std::mem::take(&mut row), row_aspect, &mut rows);
+			row_aspect = 0.0;
+		}
+	}
+	if !row.is_empty() {
+		close_row(row, row_aspect, &mut rows);
+	}
+	rows
+}
+
+#[derive(Serialize)]
+struct Row {
+	cards: Vec<Card>,
 }
 
 #[derive(Serialize)]
@@ -317,6 +730,13 @@ struct Card {
 	//sizes: String,
 	width: usize,
 	height: usize,
+	/// Position in the list of all cards
+	index: usize,
+	/// Width divided by height
+	#[serde(skip)]
+	aspect: f32,
+	/// Layout-specific inline style
+	style: String,
 	class: &'static str,
 	sub: String,
 	user: String,
@@ -324,4 +744,13 @@ struct Card {
 	permalink: String,
 	reddit_id: String,
 	url: String,
+	/// Note given when starring
+	note: String,
+	/// URL of the archived media, used if the original is gone
+	archive: String,
+	/// Post was removed from Reddit
+	removed: bool,
+	tags: Vec<String>,
+	/// Member of the star list who starred the post
+	starred_by: String,
 }